@@ -0,0 +1,365 @@
+use std::{collections::HashMap, path::Path};
+
+use gltf::{camera::Projection, image::Format, material::AlphaMode, mesh::Mode};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+use ray_tracing_in_a_weekend::{triangle_geometry::TriangleGeometry, *};
+
+use crate::worlds::world_builder::{NodeBuilder, NodeRef, WorldBuilder};
+
+// The default scene of a glTF file. Every glTF node becomes a node with its local
+// transformation, nodes sharing a mesh share its geometry.
+pub struct GltfScene<'a> {
+    pub root: NodeBuilder<'a>,
+    // The first perspective camera of the scene
+    pub camera: Option<Camera>,
+}
+
+pub fn load_gltf<'a>(wb: &'a WorldBuilder<'a>, path: &Path) -> Result<GltfScene<'a>, gltf::Error> {
+    // Resolves buffers and images embedded as data URIs, in the GLB blob or in files next to path
+    let (document, buffers, images) = gltf::import(path)?;
+    let mut loader = GltfLoader {
+        wb,
+        buffers,
+        images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: HashMap::new(),
+        camera: None,
+    };
+    let mut root = wb.new_group();
+    if let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in scene.nodes() {
+            root = root.add(loader.node(node, &Transformation::ZERO));
+        }
+    }
+    Ok(GltfScene {
+        root,
+        camera: loader.camera,
+    })
+}
+
+struct GltfLoader<'a> {
+    wb: &'a WorldBuilder<'a>,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    // By image index and whether the colors are sRGB encoded, None if the pixel format is not
+    // supported
+    textures: HashMap<(usize, bool), Option<&'a Texture<'a>>>,
+    // By material index, None is the default material
+    materials: HashMap<Option<usize>, &'a Material<'a>>,
+    meshes: HashMap<usize, NodeRef<'a>>,
+    camera: Option<Camera>,
+}
+
+impl<'a> GltfLoader<'a> {
+    fn node(&mut self, node: gltf::Node, parent_transform: &Transformation) -> NodeBuilder<'a> {
+        let wb = self.wb;
+        // A zero scale hides the node and its children, it has no inverse
+        let (_, _, scale) = node.transform().decomposed();
+        if scale.contains(&0.0) {
+            return wb.new_group();
+        }
+        let Some(transformation) = affine_from_matrix(node.transform().matrix()) else {
+            eprintln!(
+                "Skipping node {} with a singular transformation",
+                node.index()
+            );
+            return wb.new_group();
+        };
+        let full_transform = transformation.then(parent_transform);
+        if let (None, Some(camera)) = (&self.camera, node.camera()) {
+            self.camera = camera_from_gltf(&camera, &full_transform);
+        }
+
+        let mut result = wb.new_group().set_transform(transformation);
+        if let Some(mesh) = node.mesh() {
+            result = result.add(self.mesh(&mesh));
+        }
+        for child in node.children() {
+            result = result.add(self.node(child, &full_transform));
+        }
+        result
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> NodeRef<'a> {
+        if let Some(node) = self.meshes.get(&mesh.index()) {
+            return node.clone();
+        }
+        let mut triangles = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                eprintln!(
+                    "Skipping primitive of mesh {} with mode {:?}",
+                    mesh.index(),
+                    primitive.mode()
+                );
+                continue;
+            }
+            let material = self.material(&primitive.material());
+            let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions = positions.map(Point3::new_from_arr).collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .map(|n| n.map(Dir3::new_from_arr).collect::<Vec<_>>());
+            let texture_coords = reader.read_tex_coords(0).map(|t| {
+                t.into_f32()
+                    .map(|[u, v]| Vec2f::new(u, v))
+                    .collect::<Vec<_>>()
+            });
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|i| i as usize).collect::<Vec<_>>(),
+                None => (0..positions.len()).collect(),
+            };
+
+            for ids in indices.chunks_exact(3) {
+                let ids = [ids[0], ids[1], ids[2]];
+                if ids.iter().any(|&i| i >= positions.len()) {
+                    continue;
+                }
+                let positions = ids.map(|i| positions[i]);
+                let normals = match &normals {
+                    Some(normals) => ids.map(|i| normals[i]),
+                    None => {
+                        let [p0, p1, p2] = positions;
+                        [Dir3::cross(p1 - p0, p2 - p0).unit_or_else(Dir3::UP); 3]
+                    }
+                };
+                let texture_coords = match &texture_coords {
+                    Some(texture_coords) => ids.map(|i| texture_coords[i]),
+                    None => [Vec2f::ZERO; 3],
+                };
+                triangles.push((
+                    TriangleGeometry::new(positions, normals, texture_coords),
+                    material,
+                ));
+            }
+        }
+        let node = self.wb.new_mesh(triangles).build();
+        self.meshes.insert(mesh.index(), node.clone());
+        node
+    }
+
+    // Emissive materials become lights and absorbing transmissive ones glass tinted by the base
+    // color. Everything else is principled. Textures are multiplied with their factors, base color
+    // and emissive textures are sRGB encoded. Normal maps and the alpha mode apply to all of them.
+    fn material(&mut self, material: &gltf::Material) -> &'a Material<'a> {
+        if let Some(result) = self.materials.get(&material.index()) {
+            return result;
+        }
+        let wb = self.wb;
+        let pbr = material.pbr_metallic_roughness();
+        let emission = Color::new_rgb_arr(material.emissive_factor())
+            * material.emissive_strength().unwrap_or(1.0);
+        let transmission = material
+            .transmission()
+            .map_or(0.0, |t| t.transmission_factor());
+
+        let result = if emission.max_component() > 0.0 {
+            match material
+                .emissive_texture()
+                .and_then(|t| self.texture(&t.texture(), t.tex_coord(), true))
+            {
+                Some(texture) => {
+                    wb.material_diffuse_light(wb.texture_scaled(texture, emission, 1.0))
+                }
+                None => wb.material_diffuse_light_solid(emission),
+            }
+        } else {
+            let albedo = self.base_color(&pbr);
+            let volume = material.volume().filter(|_| transmission > 0.5);
+            if let Some(volume) = volume {
+                let absorption = absorption_from_color_at_distance(
+                    Color::new_rgb_arr(volume.attenuation_color()),
+                    volume.attenuation_distance(),
+                );
+                wb.material_rough_dielectric(
+                    material.ior().unwrap_or(1.5),
+                    albedo,
+                    pbr.roughness_factor(),
+                    absorption,
+                )
+            } else {
+                // Roughness is in the green and metalness in the blue channel
+                let (metallic, roughness) = match pbr
+                    .metallic_roughness_texture()
+                    .and_then(|t| self.texture(&t.texture(), t.tex_coord(), false))
+                {
+                    Some(texture) => {
+                        let factor =
+                            Color::new_rgb(1.0, pbr.roughness_factor(), pbr.metallic_factor());
+                        let texture = wb.texture_scaled(texture, factor, 1.0);
+                        (
+                            wb.texture_channel(texture, 2),
+                            wb.texture_channel(texture, 1),
+                        )
+                    }
+                    None => (
+                        wb.texture_scalar(pbr.metallic_factor()),
+                        wb.texture_scalar(pbr.roughness_factor()),
+                    ),
+                };
+                wb.material_principled(Principled {
+                    metallic,
+                    roughness,
+                    transmission: wb.texture_scalar(transmission),
+                    index_of_refraction: material.ior().unwrap_or(1.5),
+                    ..wb.principled(albedo)
+                })
+            }
+        };
+        // Masked materials are cut out by the alpha of the base color, blended ones are hit with
+        // the alpha as probability
+        let result = match material.alpha_mode() {
+            AlphaMode::Opaque => result,
+            AlphaMode::Mask => {
+                let opacity = wb.texture_channel(self.base_color(&pbr), 3);
+                let cutoff = material.alpha_cutoff().unwrap_or(0.5);
+                wb.material_cutout(result, opacity, Some(cutoff))
+            }
+            AlphaMode::Blend => {
+                let opacity = wb.texture_channel(self.base_color(&pbr), 3);
+                wb.material_cutout(result, opacity, None)
+            }
+        };
+        let result = match material
+            .normal_texture()
+            .and_then(|t| Some((self.texture(&t.texture(), t.tex_coord(), false)?, t.scale())))
+        {
+            Some((map, scale)) => wb.material_normal_mapped(result, map, scale),
+            None => result,
+        };
+        self.materials.insert(material.index(), result);
+        result
+    }
+
+    // The base color texture times the base color factor, including the alpha
+    fn base_color(&mut self, pbr: &gltf::material::PbrMetallicRoughness) -> &'a Texture<'a> {
+        let [r, g, b, a] = pbr.base_color_factor();
+        match pbr
+            .base_color_texture()
+            .and_then(|t| self.texture(&t.texture(), t.tex_coord(), true))
+        {
+            Some(texture) => self.wb.texture_scaled(texture, Color::new_rgb(r, g, b), a),
+            None => {
+                let color = self.wb.texture_solid(Color::new_rgb(r, g, b));
+                self.wb.texture_scaled(color, Color::WHITE, a)
+            }
+        }
+    }
+
+    // Only the first set of texture coordinates is loaded, textures using another one are skipped
+    fn texture(
+        &mut self,
+        texture: &gltf::Texture,
+        tex_coord: u32,
+        srgb: bool,
+    ) -> Option<&'a Texture<'a>> {
+        let index = texture.source().index();
+        if tex_coord != 0 {
+            eprintln!(
+                "Skipping texture {} using texture coordinates {tex_coord}",
+                texture.index()
+            );
+            return None;
+        }
+        if let Some(result) = self.textures.get(&(index, srgb)) {
+            return *result;
+        }
+        let result = match rgba_image(&self.images[index]) {
+            Some(image) if srgb => Some(self.wb.texture_image_srgb(image)),
+            Some(image) => Some(self.wb.texture_image(image)),
+            None => {
+                eprintln!(
+                    "Skipping image {index} with unsupported format {:?}",
+                    self.images[index].format
+                );
+                None
+            }
+        };
+        self.textures.insert((index, srgb), result);
+        result
+    }
+}
+
+// glTF matrices are column major, None if the matrix is not invertible
+fn affine_from_matrix(m: [[f32; 4]; 4]) -> Option<Transformation> {
+    let linear = [
+        [m[0][0], m[1][0], m[2][0]],
+        [m[0][1], m[1][1], m[2][1]],
+        [m[0][2], m[1][2], m[2][2]],
+    ];
+    Transformation::new_affine(linear, Dir3::new(m[3][0], m[3][1], m[3][2]))
+}
+
+// glTF cameras look along -z with y up in the space of their node.
+fn camera_from_gltf(camera: &gltf::Camera, transform: &Transformation) -> Option<Camera> {
+    let Projection::Perspective(perspective) = camera.projection() else {
+        eprintln!("Skipping orthographic camera {}", camera.index());
+        return None;
+    };
+    let viewport_height = 2.0 * (perspective.yfov() * 0.5).tan();
+    // Width divided by height, unlike Camera::aspect_ratio
+    let aspect_ratio = perspective.aspect_ratio().unwrap_or(1.0);
+    Some(
+        Camera::build()
+            .viewport(viewport_height * aspect_ratio, viewport_height)
+            .position(transform.apply_point(Point3::ORIGIN))
+            .orientation(
+                transform.apply_direction(Dir3::UP),
+                transform.apply_direction(Dir3::FORWARD),
+            )
+            .build(),
+    )
+}
+
+// 16 bit and float images are reduced to 8 bit, images without alpha are opaque.
+fn rgba_image(data: &gltf::image::Data) -> Option<RgbaImage> {
+    let (width, height) = (data.width, data.height);
+    let pixels = data.pixels.clone();
+    let image = match data.format {
+        Format::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8B8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8B8A8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R16 => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, u16s(&pixels))?)
+        }
+        Format::R16G16 => {
+            DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, u16s(&pixels))?)
+        }
+        Format::R16G16B16 => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, u16s(&pixels))?)
+        }
+        Format::R16G16B16A16 => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, u16s(&pixels))?)
+        }
+        Format::R32G32B32FLOAT => {
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, f32s(&pixels))?)
+        }
+        Format::R32G32B32A32FLOAT => {
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, f32s(&pixels))?)
+        }
+    };
+    Some(image.into_rgba8())
+}
+
+// The decoded pixels are stored in native byte order
+fn u16s(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .collect()
+}
+
+fn f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
@@ -0,0 +1,90 @@
+use image::{
+    codecs::{hdr::HdrEncoder, openexr::OpenExrEncoder},
+    ColorType, ImageEncoder, ImageError, Rgb,
+};
+use ray_tracing_in_a_weekend::*;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+// Writes the image, the format is picked by the file extension. OpenEXR (.exr), Radiance (.hdr)
+// and PFM (.pfm) keep the linear radiance, all other formats are tone mapped 8-bit images.
+pub fn save_image(
+    path: &Path,
+    pixels: &[Color],
+    image_size: Size2i,
+    tone_mapping: &ToneMapping,
+) -> Result<(), ImageError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("exr") => save_exr(path, pixels, image_size),
+        Some("hdr") => save_hdr(path, pixels, image_size),
+        Some("pfm") => save_pfm(path, pixels, image_size),
+        _ => save_ldr(path, pixels, image_size, tone_mapping),
+    }
+}
+
+fn save_ldr(
+    path: &Path,
+    pixels: &[Color],
+    image_size: Size2i,
+    tone_mapping: &ToneMapping,
+) -> Result<(), ImageError> {
+    let bytes = pixels
+        .iter()
+        .flat_map(|c| tone_mapping.to_rgb8(*c))
+        .collect::<Vec<_>>();
+    image::save_buffer(
+        path,
+        &bytes,
+        image_size.width as u32,
+        image_size.height as u32,
+        ColorType::Rgb8,
+    )
+}
+
+fn save_exr(path: &Path, pixels: &[Color], image_size: Size2i) -> Result<(), ImageError> {
+    let bytes = pixels
+        .iter()
+        .flat_map(|c| c.0.e)
+        .flat_map(|v| v.to_ne_bytes())
+        .collect::<Vec<_>>();
+    OpenExrEncoder::new(BufWriter::new(File::create(path)?)).write_image(
+        &bytes,
+        image_size.width as u32,
+        image_size.height as u32,
+        ColorType::Rgb32F,
+    )
+}
+
+fn save_hdr(path: &Path, pixels: &[Color], image_size: Size2i) -> Result<(), ImageError> {
+    let data = pixels.iter().map(|c| Rgb(c.0.e)).collect::<Vec<_>>();
+    HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(
+        &data,
+        image_size.width as usize,
+        image_size.height as usize,
+    )
+}
+
+// Portable float map: a small text header followed by the raw floats, rows are stored bottom to top.
+// The negative scale marks the data as little endian.
+fn save_pfm(path: &Path, pixels: &[Color], image_size: Size2i) -> Result<(), ImageError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(
+        out,
+        "PF\n{} {}\n-1.0\n",
+        image_size.width, image_size.height
+    )?;
+    for row in pixels.chunks(image_size.width as usize).rev() {
+        for v in row.iter().flat_map(|c| c.0.e) {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -1,5 +1,10 @@
 #![allow(dead_code)]
+mod gltf_loader;
+mod image_output;
+mod mtl_loader;
 mod obj_loader;
+mod ply_loader;
+mod stl_loader;
 mod worlds;
 
 use image::ImageError;
@@ -8,12 +13,39 @@ use ray_tracing_in_a_weekend::*;
 use std::{path::Path, thread};
 
 fn main() -> Result<(), ImageError> {
-    let args: Vec<String> = std::env::args().collect();
+    // Positional arguments followed by optional --name=value flags
+    let (flags, args): (Vec<String>, Vec<String>) =
+        std::env::args().partition(|a| a.starts_with("--"));
+    let mut tone_mapping = ToneMapping::default();
+    for flag in &flags {
+        let (name, value) = flag.split_once('=').unwrap_or((flag, ""));
+        match name {
+            "--tone-mapping" => {
+                tone_mapping.operator = value.parse().unwrap_or_else(|e: String| usage_error(&e))
+            }
+            "--exposure" => {
+                tone_mapping.exposure = value
+                    .parse()
+                    .unwrap_or_else(|_| usage_error(&format!("Invalid exposure `{value}`")))
+            }
+            _ => usage_error(&format!("Unknown flag {flag}")),
+        }
+    }
 
+    if args.len() != 6 {
+        usage_error("Expected five arguments");
+    }
     let path = Path::new(&args[1]);
-    let image_width = args[2].parse::<i32>().unwrap();
-    let samples_per_pixel = args[3].parse::<usize>().unwrap();
-    let max_depth = args[4].parse::<i32>().unwrap();
+    let number = |index: usize, name: &str| {
+        args[index]
+            .parse::<i32>()
+            .ok()
+            .filter(|n| *n > 0)
+            .unwrap_or_else(|| usage_error(&format!("Invalid {name} `{}`", args[index])))
+    };
+    let image_width = number(2, "width");
+    let samples_per_pixel = number(3, "sample count") as usize;
+    let max_depth = number(4, "depth");
     let world_name = args[5].as_str();
 
     let thread_count = thread::available_parallelism().map_or(1, |x| x.get());
@@ -22,43 +54,64 @@ fn main() -> Result<(), ImageError> {
     let mut arena = bumpalo::Bump::new();
     let wb = worlds::world_builder::WorldBuilder::new(&mut arena);
     let mut rng = TRng::from_seed([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
-    let world = match world_name {
-        "demo:cornell_box" => worlds::demo_worlds::create_world_cornell_box,
-        "demo:cornell_box_smoke" => worlds::demo_worlds::create_world_cornell_box_smoke,
-        "demo:defocus_blur" => worlds::demo_worlds::create_world_defocus_blur,
-        "demo:simple_plane" => worlds::demo_worlds::create_world_simple_plane,
-        "demo:earth_mapped" => worlds::demo_worlds::create_world_earth_mapped,
-        "demo:suzanne" => worlds::demo_worlds::create_world_suzanne,
-        "demo:moving_spheres" => worlds::demo_worlds::create_world_moving_spheres,
-        "demo:final_scene1" => worlds::demo_worlds::create_world_final_scene1,
-        "demo:perlin_spheres" => worlds::demo_worlds::create_world_perlin_spheres,
-        "demo:final_scene2" => worlds::demo_worlds::create_world_final_scene2,
-        _ => panic!(),
-    }(&wb, &mut rng);
+    // Either a built in demo, a glTF file or the path of a scene file
+    let world = if world_name.starts_with("demo:") {
+        let create_world = match world_name {
+            "demo:cornell_box" => worlds::demo_worlds::create_world_cornell_box,
+            "demo:cornell_box_smoke" => worlds::demo_worlds::create_world_cornell_box_smoke,
+            "demo:defocus_blur" => worlds::demo_worlds::create_world_defocus_blur,
+            "demo:simple_plane" => worlds::demo_worlds::create_world_simple_plane,
+            "demo:two_lamps" => worlds::demo_worlds::create_world_two_lamps,
+            "demo:shape_lamps" => worlds::demo_worlds::create_world_shape_lamps,
+            "demo:earth_mapped" => worlds::demo_worlds::create_world_earth_mapped,
+            "demo:suzanne" => worlds::demo_worlds::create_world_suzanne,
+            "demo:suzanne_instances" => worlds::demo_worlds::create_world_suzanne_instances,
+            "demo:transformations" => worlds::demo_worlds::create_world_transformations,
+            "demo:moving_spheres" => worlds::demo_worlds::create_world_moving_spheres,
+            "demo:final_scene1" => worlds::demo_worlds::create_world_final_scene1,
+            "demo:perlin_spheres" => worlds::demo_worlds::create_world_perlin_spheres,
+            "demo:final_scene2" => worlds::demo_worlds::create_world_final_scene2,
+            "demo:conductors" => worlds::demo_worlds::create_world_conductors,
+            "demo:rough_glass" => worlds::demo_worlds::create_world_rough_glass,
+            "demo:absorbing_glass" => worlds::demo_worlds::create_world_absorbing_glass,
+            "demo:coated" => worlds::demo_worlds::create_world_coated,
+            "demo:principled" => worlds::demo_worlds::create_world_principled,
+            "demo:oren_nayar" => worlds::demo_worlds::create_world_oren_nayar,
+            "demo:bump_mapping" => worlds::demo_worlds::create_world_bump_mapping,
+            "demo:cutout" => worlds::demo_worlds::create_world_cutout,
+            "demo:mix" => worlds::demo_worlds::create_world_mix,
+            _ => usage_error(&format!("Unknown demo world {world_name}")),
+        };
+        create_world(&wb, &mut rng)
+    } else {
+        let world_path = Path::new(world_name);
+        let loaded = if worlds::scene_file::is_gltf(world_path) {
+            worlds::scene_file::load_gltf_file(&wb, world_path)
+        } else {
+            worlds::scene_file::load_scene_file(&wb, &mut rng, world_path)
+        };
+        loaded.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1)
+        })
+    };
     let image_size = Size2i::new(
         image_width,
         (image_width as f32 * world.camera.aspect_ratio()) as i32,
     );
 
-    let pixels = crate::rendering::render(
-        image_size,
-        thread_count,
-        samples_per_pixel,
-        max_depth,
-        &world,
-        RenderMode::Default,
-    );
+    let settings = RenderSettings::new(samples_per_pixel, max_depth);
+    let pixels = crate::rendering::render(image_size, thread_count, &settings, &world);
 
     eprintln!("Saving image...");
-    let bytes = pixels
-        .iter()
-        .flat_map(|c| c.to_rgb8_gamma2())
-        .collect::<Vec<_>>();
-    image::save_buffer(
-        path,
-        &bytes,
-        image_size.width as u32,
-        image_size.height as u32,
-        image::ColorType::Rgb8,
-    )
+    image_output::save_image(path, &pixels, image_size, &tone_mapping)
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!(
+        "Usage: ray_tracing_in_a_weekend_app OUTPUT WIDTH SAMPLES DEPTH WORLD \
+         [--tone-mapping=OPERATOR] [--exposure=STOPS]"
+    );
+    std::process::exit(1)
 }
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use ray_tracing_in_a_weekend::Color;
+
+use crate::obj_loader::{ObjError, ObjSyntaxError};
+
+// The subset of a Wavefront material which maps onto our materials
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    // Kd
+    pub diffuse: Color,
+    // Ks
+    pub specular: Color,
+    // Ns
+    pub specular_exponent: f32,
+    // Ni
+    pub index_of_refraction: f32,
+    // d, or 1 - Tr
+    pub dissolve: f32,
+    // Ke
+    pub emission: Color,
+    // map_Kd, relative to the material library
+    pub diffuse_map: Option<String>,
+    // illum
+    pub illumination_model: Option<u32>,
+    // The physically based extension, Pr, Pm, Ps, Pc and Pcr
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub sheen: Option<f32>,
+    pub clearcoat: Option<f32>,
+    pub clearcoat_roughness: Option<f32>,
+}
+
+impl MtlMaterial {
+    pub fn is_physically_based(&self) -> bool {
+        [
+            self.roughness,
+            self.metallic,
+            self.sheen,
+            self.clearcoat,
+            self.clearcoat_roughness,
+        ]
+        .iter()
+        .any(Option::is_some)
+    }
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new_rgb(0.8, 0.8, 0.8),
+            specular: Color::BLACK,
+            specular_exponent: 0.0,
+            index_of_refraction: 1.0,
+            dissolve: 1.0,
+            emission: Color::BLACK,
+            diffuse_map: None,
+            illumination_model: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+        }
+    }
+}
+
+pub fn load_mtl<R: std::io::BufRead>(reader: R) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (line_id, maybe_line) in reader.lines().enumerate() {
+        let line = maybe_line.map_err(ObjError::IoError)?;
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest = line.trim_start()[keyword.len()..].trim();
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((rest.to_string(), MtlMaterial::default()));
+            continue;
+        }
+        // Statements before the first newmtl have nothing to apply to
+        let Some((_, material)) = &mut current else {
+            continue;
+        };
+        let parse = |t: Option<&str>| {
+            t.ok_or(ObjSyntaxError::MissingValue)?
+                .parse::<f32>()
+                .map_err(ObjSyntaxError::F32Format)
+        };
+        let color = |tokens: &mut std::str::SplitWhitespace| {
+            let r = parse(tokens.next())?;
+            // A single value is used for all channels
+            match tokens.next() {
+                Some(g) => Ok(Color::new_rgb(r, parse(Some(g))?, parse(tokens.next())?)),
+                None => Ok(Color::new_rgb(r, r, r)),
+            }
+        };
+        let result = match keyword {
+            "Kd" => color(&mut tokens).map(|c| material.diffuse = c),
+            "Ks" => color(&mut tokens).map(|c| material.specular = c),
+            "Ke" => color(&mut tokens).map(|c| material.emission = c),
+            "Ns" => parse(tokens.next()).map(|v| material.specular_exponent = v),
+            "Ni" => parse(tokens.next()).map(|v| material.index_of_refraction = v),
+            "d" => parse(tokens.next()).map(|v| material.dissolve = v),
+            "Tr" => parse(tokens.next()).map(|v| material.dissolve = 1.0 - v),
+            "illum" => parse(tokens.next()).map(|v| material.illumination_model = Some(v as u32)),
+            "Pr" => parse(tokens.next()).map(|v| material.roughness = Some(v)),
+            "Pm" => parse(tokens.next()).map(|v| material.metallic = Some(v)),
+            "Ps" => parse(tokens.next()).map(|v| material.sheen = Some(v)),
+            "Pc" => parse(tokens.next()).map(|v| material.clearcoat = Some(v)),
+            "Pcr" => parse(tokens.next()).map(|v| material.clearcoat_roughness = Some(v)),
+            // Options like -s or -o precede the file name
+            "map_Kd" => {
+                material.diffuse_map = tokens.last().map(str::to_string);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        result.map_err(|e| ObjError::Syntax(line_id + 1, e))?;
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
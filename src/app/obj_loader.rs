@@ -1,271 +1,363 @@
-use std::str::Chars;
-
-use ray_tracing_in_a_weekend::{
-    triangle_geometry::TriangleGeometry, Dir3, Point3, Vec2, Vec2f, Vec3,
+use std::{
+    collections::HashMap,
+    num::{ParseFloatError, ParseIntError},
+    ops::Range,
 };
 
-pub fn load_obj_mesh<R: std::io::BufRead>(reader: R) -> Result<Vec<TriangleGeometry>> {
-    let mut loader = ObjLoader {
-        positions: Vec::new(),
-        normals: Vec::new(),
-        texture_coords: Vec::new(),
-        triangles: Vec::new(),
-    };
-    for maybe_line in reader.lines() {
-        match maybe_line {
-            Ok(line) => loader.parse_line(&line)?,
-            Err(err) => return Err(ScannerError::IoError(err)),
-        }
-    }
+use ray_tracing_in_a_weekend::{triangle_geometry::TriangleGeometry, Dir3, Point3, Vec2f};
 
-    Ok(loader.triangles)
+pub struct ObjMesh {
+    pub triangles: Vec<TriangleGeometry>,
+    // Runs of consecutive triangles which share object, groups and material
+    pub segments: Vec<ObjSegment>,
+    // File names from mtllib lines
+    pub material_libraries: Vec<String>,
 }
 
-struct ObjLoader {
-    positions: Vec<Point3>,
-    normals: Vec<Dir3>,
-    texture_coords: Vec<Vec2f>,
-    triangles: Vec<TriangleGeometry>,
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjSegment {
+    pub object_name: Option<String>,
+    pub group_names: Vec<String>,
+    pub material_name: Option<String>,
+    pub triangles: Range<usize>,
 }
 
-struct Scanner<'a> {
-    chars: Chars<'a>,
-    str: &'a str,
-    peeked: Option<char>,
+pub fn load_obj_mesh<R: std::io::BufRead>(reader: R) -> Result<ObjMesh> {
+    let mut loader = ObjLoader::default();
+    // Lines ending with a backslash continue on the next line
+    let mut pending = String::new();
+    for (line_id, maybe_line) in reader.lines().enumerate() {
+        let line = maybe_line.map_err(ObjError::IoError)?;
+        if let Some(start) = line.strip_suffix('\\') {
+            pending.push_str(start);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(&line);
+        loader
+            .parse_line(&pending)
+            .map_err(|e| ObjError::Syntax(line_id + 1, e))?;
+        pending.clear();
+    }
+
+    Ok(loader.finish())
 }
 
 #[derive(Debug)]
-pub enum ScannerError {
+pub enum ObjError {
     IoError(std::io::Error),
-    UnexpectedEndOfFile,
-    ExpectedAtLeastOnWhitespace,
-    ExpectedDigits,
-    UsizeFormat(<usize as std::str::FromStr>::Err),
-    F32Format(<f32 as std::str::FromStr>::Err),
-    PosIdOutOfRange(usize),
-    NorIdOutOfRange(usize),
-    TexIdOutOfRange(usize),
+    // The line number starting at one and the error
+    Syntax(usize, ObjSyntaxError),
 }
 
-type Vertex = (usize, Option<usize>, Option<usize>);
-enum LineType {
-    V(Vec3<f32>),
-    Vn(Vec3<f32>),
-    Vt(Vec2<f32>),
-    F(Vec<Vertex>),
-    Unknown,
-    Empty,
-    Comment,
-    Object,
+#[derive(Debug)]
+pub enum ObjSyntaxError {
+    MissingValue,
+    F32Format(ParseFloatError),
+    IndexFormat(ParseIntError),
+    ZeroIndex,
+    TooFewVertices(usize),
+    PosIdOutOfRange(isize),
+    NorIdOutOfRange(isize),
+    TexIdOutOfRange(isize),
 }
 
-type Result<T> = std::result::Result<T, ScannerError>;
-
-impl<'a> Scanner<'a> {
-    pub fn new(mut chars: Chars<'a>) -> Scanner<'a> {
-        let str = chars.as_str();
-        let peeked = chars.next();
-        Scanner { chars, str, peeked }
-    }
-    pub fn parse_line(chars: Chars<'a>) -> Result<LineType> {
-        Self::new(chars).take_line_type()
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::IoError(err) => write!(f, "{err}"),
+            ObjError::Syntax(line, err) => write!(f, "line {line}: {err:?}"),
+        }
     }
+}
 
-    pub fn try_take(&mut self) -> Option<char> {
-        let result = self.peeked;
-        self.str = self.chars.as_str();
-        self.peeked = self.chars.next();
-        result
-    }
-    pub fn try_take_char(&mut self, c: char) -> Option<char> {
-        self.try_take_char_fn(|x| x == c)
-    }
-    pub fn try_take_char_fn<F: Fn(char) -> bool>(&mut self, c: F) -> Option<char> {
-        if let Some(x) = self.peeked {
-            if c(x) {
-                self.take().unwrap();
-                Some(x)
-            } else {
-                None
+impl std::error::Error for ObjError {}
+
+type Result<T> = std::result::Result<T, ObjError>;
+
+// Indices into the position, texture coordinate and normal lists
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    pos_id: usize,
+    uv_id: Option<usize>,
+    nor_id: Option<usize>,
+}
+
+struct Face {
+    corners: [Corner; 3],
+    // Zero if smoothing is off
+    smoothing_group: u32,
+}
+
+#[derive(Default)]
+struct ObjLoader {
+    positions: Vec<Point3>,
+    normals: Vec<Dir3>,
+    texture_coords: Vec<Vec2f>,
+    faces: Vec<Face>,
+    current: ObjSegment,
+    smoothing_group: u32,
+    segments: Vec<ObjSegment>,
+    material_libraries: Vec<String>,
+}
+
+impl ObjLoader {
+    fn parse_line(&mut self, line: &str) -> std::result::Result<(), ObjSyntaxError> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(());
+        };
+        // Everything after the keyword, names may contain spaces
+        let rest = line.trim_start()[keyword.len()..].trim();
+        match keyword {
+            "v" => {
+                // An optional w or vertex color may follow
+                let [x, y, z] = take_f32s(&mut tokens)?;
+                self.positions.push(Point3::new(x, y, z));
             }
-        } else {
-            None
-        }
-    }
-    pub fn peek(&self) -> Option<char> {
-        self.peeked
-    }
-    pub fn take(&mut self) -> Result<char> {
-        if let Some(x) = self.try_take() {
-            Ok(x)
-        } else {
-            Err(ScannerError::UnexpectedEndOfFile)
-        }
-    }
-    pub fn take_at_least_one_whitespace(&mut self) -> Result<()> {
-        if self.try_take_char(' ').is_none() {
-            return Err(ScannerError::ExpectedAtLeastOnWhitespace);
+            "vn" => {
+                let [x, y, z] = take_f32s(&mut tokens)?;
+                self.normals.push(Dir3::new(x, y, z));
+            }
+            "vt" => {
+                let u = take_f32(tokens.next())?;
+                let v = tokens.next().map_or(Ok(0.0), |t| take_f32(Some(t)))?;
+                // Image rows go from top to bottom
+                self.texture_coords.push(Vec2f::new(u, 1.0 - v));
+            }
+            "f" => {
+                let corners = tokens
+                    .map(|t| self.take_corner(t))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(ObjSyntaxError::TooFewVertices(corners.len()));
+                }
+                self.add_face(&corners);
+            }
+            "o" => self.current.object_name = (!rest.is_empty()).then(|| rest.to_string()),
+            "g" => self.current.group_names = tokens.map(str::to_string).collect(),
+            "usemtl" => self.current.material_name = (!rest.is_empty()).then(|| rest.to_string()),
+            "s" => {
+                self.smoothing_group = match rest {
+                    "" | "off" | "0" => 0,
+                    // Some exporters write "s on"
+                    group => group.parse::<u32>().unwrap_or(1),
+                }
+            }
+            "mtllib" => self.material_libraries.extend(tokens.map(str::to_string)),
+            // Lines, points, free form geometry and unknown statements are skipped
+            _ => {}
         }
-        self.take_any_whitespace()
-    }
-    pub fn take_any_whitespace(&mut self) -> Result<()> {
-        while self.try_take_char(' ').is_some() {}
         Ok(())
     }
-    pub fn take_str_digits(&mut self) -> Result<&str> {
-        let start = self.str;
-        if self.try_take_char_fn(char::is_numeric).is_none() {
-            return Err(ScannerError::ExpectedDigits);
-        }
 
-        while self.try_take_char_fn(char::is_numeric).is_some() {}
-        Ok(&start[..start.len() - self.str.len()])
-    }
-    pub fn take_usize(&mut self) -> Result<usize> {
-        let digits = self.take_str_digits();
-        digits.and_then(|str| str.parse::<usize>().map_err(ScannerError::UsizeFormat))
+    // Parses pos, pos/uv, pos//nor or pos/uv/nor, negative indices are relative to the end.
+    fn take_corner(&self, token: &str) -> std::result::Result<Corner, ObjSyntaxError> {
+        let mut parts = token.split('/');
+        let pos_id = resolve_index(parts.next(), self.positions.len())?
+            .ok_or(ObjSyntaxError::MissingValue)?
+            .map_err(ObjSyntaxError::PosIdOutOfRange)?;
+        let uv_id = resolve_index(parts.next(), self.texture_coords.len())?
+            .transpose()
+            .map_err(ObjSyntaxError::TexIdOutOfRange)?;
+        let nor_id = resolve_index(parts.next(), self.normals.len())?
+            .transpose()
+            .map_err(ObjSyntaxError::NorIdOutOfRange)?;
+        Ok(Corner {
+            pos_id,
+            uv_id,
+            nor_id,
+        })
     }
-    pub fn take_float_digits(&mut self) -> Result<&str> {
-        let start = self.str;
-        self.try_take_char('-');
-        self.take_str_digits()?;
-        if self.try_take_char('.').is_some() {
-            self.take_str_digits()?;
+
+    fn add_face(&mut self, corners: &[Corner]) {
+        let start = self.faces.len();
+        if self.segments.last().is_none_or(|s| {
+            s.object_name != self.current.object_name
+                || s.group_names != self.current.group_names
+                || s.material_name != self.current.material_name
+        }) {
+            self.segments.push(ObjSegment {
+                triangles: start..start,
+                ..self.current.clone()
+            });
+        }
+
+        let points = corners
+            .iter()
+            .map(|c| self.positions[c.pos_id])
+            .collect::<Vec<_>>();
+        for [a, b, c] in triangulate(&points) {
+            self.faces.push(Face {
+                corners: [corners[a], corners[b], corners[c]],
+                smoothing_group: self.smoothing_group,
+            });
         }
-        Ok(&start[..start.len() - self.str.len()])
+        self.segments.last_mut().unwrap().triangles.end = self.faces.len();
     }
-    pub fn take_f32(&mut self) -> Result<f32> {
-        let digits = self.take_float_digits();
-        digits.and_then(|str| str.parse::<f32>().map_err(ScannerError::F32Format))
+
+    fn face_normal(&self, face: &Face) -> Dir3 {
+        let [p0, p1, p2] = face.corners.map(|c| self.positions[c.pos_id]);
+        Dir3::cross(p1 - p0, p2 - p0)
     }
-    pub fn take_line_type(&mut self) -> Result<LineType> {
-        match self.try_take_char_fn(|c| (c == 'v' || c == 'f' || c == '#' || c == 'o')) {
-            Some('v') => match self.try_take_char_fn(|c| (c == 'n' || c == 't')) {
-                Some('n') => {
-                    let v = self.take_vec3_f32()?;
-                    Ok(LineType::Vn(v))
-                }
-                Some('t') => {
-                    let v = self.take_vec2_f32()?;
-                    Ok(LineType::Vt(v))
-                }
-                _ => {
-                    let v = self.take_vec3_f32()?;
-                    Ok(LineType::V(v))
-                }
-            },
-            Some('f') => {
-                self.take_at_least_one_whitespace()?;
-                let mut vertex_ids = Vec::new();
-                while self.peek() != None {
-                    let ids = self.take_vertex()?;
-                    self.take_any_whitespace()?;
-                    vertex_ids.push(ids);
+
+    // Missing normals are flat, or averaged over all faces of the smoothing group sharing the position.
+    fn finish(self) -> ObjMesh {
+        let mut smooth_normals = HashMap::<(usize, u32), Dir3>::new();
+        for face in &self.faces {
+            if face.smoothing_group != 0 && face.corners.iter().any(|c| c.nor_id.is_none()) {
+                // Weighted by the area
+                let normal = self.face_normal(face);
+                for corner in &face.corners {
+                    *smooth_normals
+                        .entry((corner.pos_id, face.smoothing_group))
+                        .or_insert(Dir3::ZERO) += normal;
                 }
-                Ok(LineType::F(vertex_ids))
             }
-            Some('#') => Ok(LineType::Comment),
-            Some('o') => Ok(LineType::Object),
-            _ => Ok(LineType::Unknown),
+        }
+
+        let triangles = self
+            .faces
+            .iter()
+            .map(|face| {
+                let flat_normal = normalize_or(self.face_normal(face), Dir3::UP);
+                TriangleGeometry::new(
+                    face.corners.map(|c| self.positions[c.pos_id]),
+                    face.corners.map(|c| match c.nor_id {
+                        Some(id) => normalize_or(self.normals[id], flat_normal),
+                        None if face.smoothing_group != 0 => normalize_or(
+                            smooth_normals[&(c.pos_id, face.smoothing_group)],
+                            flat_normal,
+                        ),
+                        None => flat_normal,
+                    }),
+                    face.corners
+                        .map(|c| c.uv_id.map_or(Vec2f::ZERO, |id| self.texture_coords[id])),
+                )
+            })
+            .collect();
+
+        ObjMesh {
+            triangles,
+            segments: self.segments,
+            material_libraries: self.material_libraries,
         }
     }
-    pub fn take_vec3_f32(&mut self) -> Result<Vec3<f32>> {
-        self.take_at_least_one_whitespace()?;
-        let x = self.take_f32()?;
-        self.take_at_least_one_whitespace()?;
-        let y = self.take_f32()?;
-        self.take_at_least_one_whitespace()?;
-        let z = self.take_f32()?;
-        Ok(Vec3::new(x, y, z))
-    }
-    pub fn take_vec2_f32(&mut self) -> Result<Vec2f> {
-        self.take_at_least_one_whitespace()?;
-        let x = self.take_f32()?;
-        self.take_at_least_one_whitespace()?;
-        let y = self.take_f32()?;
-        Ok(Vec2f::new(x, y))
+}
+
+fn take_f32(token: Option<&str>) -> std::result::Result<f32, ObjSyntaxError> {
+    token
+        .ok_or(ObjSyntaxError::MissingValue)?
+        .parse::<f32>()
+        .map_err(ObjSyntaxError::F32Format)
+}
+
+fn take_f32s<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut I,
+) -> std::result::Result<[f32; 3], ObjSyntaxError> {
+    Ok([
+        take_f32(tokens.next())?,
+        take_f32(tokens.next())?,
+        take_f32(tokens.next())?,
+    ])
+}
+
+// None for an absent or empty index, otherwise the zero based index or the out of range index.
+fn resolve_index(
+    part: Option<&str>,
+    count: usize,
+) -> std::result::Result<Option<std::result::Result<usize, isize>>, ObjSyntaxError> {
+    let Some(part) = part.filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    let index = part.parse::<isize>().map_err(ObjSyntaxError::IndexFormat)?;
+    let resolved = match index {
+        0 => return Err(ObjSyntaxError::ZeroIndex),
+        i if i > 0 => i - 1,
+        i => count as isize + i,
+    };
+    if (0..count as isize).contains(&resolved) {
+        Ok(Some(Ok(resolved as usize)))
+    } else {
+        Ok(Some(Err(index)))
     }
-    pub fn take_vertex(&mut self) -> Result<(usize, Option<usize>, Option<usize>)> {
-        let pos_id = self.take_usize()? - 1;
-        self.take_any_whitespace()?;
-        if self.try_take_char('/').is_some() {
-            self.take_any_whitespace()?;
-            if self.try_take_char('/').is_some() {
-                self.take_any_whitespace()?;
-                let nor_id = self.take_usize()? - 1;
-                Ok((pos_id, None, Some(nor_id)))
-            } else {
-                let uv_id = self.take_usize()? - 1;
-                self.take_any_whitespace()?;
-                if self.try_take_char('/').is_some() {
-                    self.take_any_whitespace()?;
-                    let nor_id = self.take_usize()? - 1;
-                    Ok((pos_id, Some(uv_id), Some(nor_id)))
-                } else {
-                    Ok((pos_id, Some(uv_id), None))
-                }
-            }
-        } else {
-            Ok((pos_id, None, None))
-        }
+}
+
+pub fn normalize_or(v: Dir3, default: Dir3) -> Dir3 {
+    let length = v.length();
+    if length > 0.0 && length.is_finite() {
+        v / length
+    } else {
+        default
     }
 }
 
-impl ObjLoader {
-    fn parse_line(&mut self, str: &str) -> Result<()> {
-        match Scanner::parse_line(str.chars())? {
-            LineType::V(v) => {
-                self.positions.push(Point3(v));
-            }
-            LineType::Vn(v) => {
-                self.normals.push(Dir3(v));
-            }
-            LineType::Vt(v) => {
-                self.texture_coords.push(v);
-            }
-            LineType::F(vertex_ids) => {
-                let mut tri = TriangleGeometry {
-                    positions: [Point3::ORIGIN, Point3::ORIGIN, Point3::ORIGIN],
-                    normals: [Dir3::ZERO, Dir3::ZERO, Dir3::ZERO],
-                    texture_coords: [Vec2f::ZERO, Vec2f::ZERO, Vec2f::ZERO],
-                };
-                for (i, ids) in vertex_ids.iter().enumerate() {
-                    let position = *self
-                        .positions
-                        .get(ids.0)
-                        .ok_or(ScannerError::PosIdOutOfRange(ids.0))?;
-                    let uv = match ids.1 {
-                        Some(i) => *self
-                            .texture_coords
-                            .get(i)
-                            .ok_or(ScannerError::TexIdOutOfRange(i))?,
-                        None => Vec2f::ZERO,
-                    };
-                    let normal = match ids.2 {
-                        Some(i) => *self
-                            .normals
-                            .get(i)
-                            .ok_or(ScannerError::NorIdOutOfRange(i))?,
-                        None => todo!(),
-                    };
-                    if i < 2 {
-                        tri.positions[i] = position;
-                        tri.normals[i] = normal;
-                        tri.texture_coords[i] = uv;
-                    } else {
-                        tri.positions[1] = tri.positions[2];
-                        tri.normals[1] = tri.normals[2];
-                        tri.texture_coords[1] = tri.texture_coords[2];
+// Splits a planar polygon into triangles by ear clipping, so concave polygons work as well.
+// Returns indices into points, falls back to a fan for degenerate polygons, including ones with
+// non-finite coordinates.
+pub fn triangulate(points: &[Point3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
 
-                        tri.positions[2] = position;
-                        tri.normals[2] = normal;
-                        tri.texture_coords[2] = uv;
-                        self.triangles.push(tri);
-                    }
-                }
-            }
-            _ => {}
-        }
-        Ok(())
+    // Newell's method for the polygon normal, then project along its dominant axis
+    let mut normal = [0.0f32; 3];
+    for i in 0..n {
+        let a = points[i].0.e;
+        let b = points[(i + 1) % n].0.e;
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    let axis = (0..3)
+        .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+        .unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    // Counterclockwise after the projection
+    let orientation = normal[axis].signum();
+    let projected = points
+        .iter()
+        .map(|p| (p.0.e[u], p.0.e[v]))
+        .collect::<Vec<_>>();
+    let cross = |a: usize, b: usize, c: usize| {
+        let (pa, pb, pc) = (projected[a], projected[b], projected[c]);
+        ((pb.0 - pa.0) * (pc.1 - pa.1) - (pb.1 - pa.1) * (pc.0 - pa.0)) * orientation
+    };
+
+    let mut remaining = (0..n).collect::<Vec<_>>();
+    let mut result = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let ear = (0..len).find(|i| {
+            let (a, b, c) = (
+                remaining[(i + len - 1) % len],
+                remaining[*i],
+                remaining[(i + 1) % len],
+            );
+            cross(a, b, c) > 0.0
+                && remaining.iter().all(|&p| {
+                    p == a
+                        || p == b
+                        || p == c
+                        || cross(a, b, p) < 0.0
+                        || cross(b, c, p) < 0.0
+                        || cross(c, a, p) < 0.0
+                })
+        });
+        let Some(i) = ear else {
+            break;
+        };
+        result.push([
+            remaining[(i + len - 1) % len],
+            remaining[i],
+            remaining[(i + 1) % len],
+        ]);
+        remaining.remove(i);
+    }
+    for i in 1..remaining.len() - 1 {
+        result.push([remaining[0], remaining[i], remaining[i + 1]]);
     }
+    result
 }
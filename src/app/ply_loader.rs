@@ -0,0 +1,448 @@
+use std::io::{BufRead, Read};
+
+use ray_tracing_in_a_weekend::{
+    mesh_geometry::MeshGeometry, srgb_decode, Color, Dir3, Point3, Vec2f,
+};
+
+use crate::obj_loader::{normalize_or, triangulate};
+
+pub struct PlyMesh {
+    pub geometry: MeshGeometry,
+    // One per vertex, if the vertices have red, green and blue
+    pub vertex_colors: Option<Vec<Color>>,
+}
+
+// Reads ASCII as well as little and big endian binary files. Vertices need x, y and z, the
+// normal (nx, ny, nz), texture coordinates (u, v or s, t) and color (red, green, blue) are
+// optional. Faces are polygons in the vertex_indices list. Other elements and properties are
+// skipped.
+pub fn load_ply_mesh<R: BufRead>(mut reader: R) -> Result<PlyMesh> {
+    let header = read_header(&mut reader)?;
+    match header.format {
+        Format::Ascii => read_body(
+            &header,
+            AsciiValues {
+                lines: reader.lines(),
+                tokens: Vec::new().into_iter(),
+            },
+        ),
+        Format::BinaryLittleEndian => read_body(
+            &header,
+            BinaryValues {
+                reader,
+                big_endian: false,
+            },
+        ),
+        Format::BinaryBigEndian => read_body(
+            &header,
+            BinaryValues {
+                reader,
+                big_endian: true,
+            },
+        ),
+    }
+}
+
+#[derive(Debug)]
+pub enum PlyError {
+    IoError(std::io::Error),
+    // The line number starting at one and the problem
+    Header(usize, String),
+    UnexpectedEnd,
+    ValueFormat(String),
+    MissingProperty(&'static str),
+    IndexOutOfRange(usize),
+}
+
+impl std::fmt::Display for PlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlyError::IoError(err) => write!(f, "{err}"),
+            PlyError::Header(line, message) => write!(f, "header line {line}: {message}"),
+            PlyError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            PlyError::ValueFormat(token) => write!(f, "invalid value `{token}`"),
+            PlyError::MissingProperty(name) => write!(f, "missing vertex property {name}"),
+            PlyError::IndexOutOfRange(index) => write!(f, "vertex index {index} out of range"),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+type Result<T> = std::result::Result<T, PlyError>;
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // Integer colors use the full range of their type and are sRGB encoded like 8 bit images,
+    // float colors are linear.
+    fn decode_color(self, [r, g, b]: [f32; 3]) -> Color {
+        let scale = match self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => return Color::new_rgb(r, g, b),
+        };
+        Color::new_rgb_arr([r, g, b].map(|c| srgb_decode(c * scale)))
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<Header> {
+    let mut format = None;
+    let mut elements = Vec::<Element>::new();
+    let mut line = String::new();
+    let mut line_id = 0;
+    loop {
+        line.clear();
+        line_id += 1;
+        if reader.read_line(&mut line).map_err(PlyError::IoError)? == 0 {
+            return Err(PlyError::UnexpectedEnd);
+        }
+        let error = |message: &str| PlyError::Header(line_id, message.to_string());
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if line_id == 1 {
+            if tokens != ["ply"] {
+                return Err(error("not a PLY file"));
+            }
+            continue;
+        }
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error("unknown format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error("invalid element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let kind = match (ScalarType::parse(count), ScalarType::parse(item)) {
+                    (Some(count), Some(item)) => PropertyKind::List { count, item },
+                    _ => return Err(error("unknown property type")),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property without element"))?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        kind,
+                    });
+            }
+            ["property", ty, name] => {
+                let ty = ScalarType::parse(ty).ok_or_else(|| error("unknown property type"))?;
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property without element"))?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        kind: PropertyKind::Scalar(ty),
+                    });
+            }
+            ["end_header"] => {
+                return Ok(Header {
+                    format: format.ok_or_else(|| error("end of header without format"))?,
+                    elements,
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error("unexpected statement")),
+        }
+    }
+}
+
+trait ValueReader {
+    fn read(&mut self, ty: ScalarType) -> Result<f64>;
+}
+
+struct AsciiValues<R> {
+    lines: std::io::Lines<R>,
+    tokens: std::vec::IntoIter<String>,
+}
+
+impl<R: BufRead> ValueReader for AsciiValues<R> {
+    fn read(&mut self, _ty: ScalarType) -> Result<f64> {
+        loop {
+            if let Some(token) = self.tokens.next() {
+                return token.parse().map_err(|_| PlyError::ValueFormat(token));
+            }
+            let line = self
+                .lines
+                .next()
+                .ok_or(PlyError::UnexpectedEnd)?
+                .map_err(PlyError::IoError)?;
+            self.tokens = line
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+}
+
+struct BinaryValues<R> {
+    reader: R,
+    big_endian: bool,
+}
+
+impl<R: Read> ValueReader for BinaryValues<R> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64> {
+        let mut buffer = [0u8; 8];
+        let bytes = &mut buffer[..ty.size()];
+        self.reader.read_exact(bytes).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                PlyError::UnexpectedEnd
+            } else {
+                PlyError::IoError(e)
+            }
+        })?;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        let b = &buffer;
+        Ok(match ty {
+            ScalarType::Int8 => b[0] as i8 as f64,
+            ScalarType::UInt8 => b[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(*b),
+        })
+    }
+}
+
+#[derive(Default)]
+struct PlyData {
+    positions: Vec<Point3>,
+    normals: Vec<Dir3>,
+    texture_coords: Vec<Vec2f>,
+    colors: Vec<Color>,
+    faces: Vec<[u32; 3]>,
+}
+
+fn read_body<V: ValueReader>(header: &Header, mut values: V) -> Result<PlyMesh> {
+    let mut data = PlyData::default();
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(element, &mut values, &mut data)?,
+            "face" => read_faces(element, &mut values, &mut data)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        read_property(&property.kind, &mut values, &mut Vec::new())?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(data.finish())
+}
+
+// Scalars are appended to row, lists are read and dropped.
+fn read_property<V: ValueReader>(
+    kind: &PropertyKind,
+    values: &mut V,
+    row: &mut Vec<f64>,
+) -> Result<()> {
+    match *kind {
+        PropertyKind::Scalar(ty) => row.push(values.read(ty)?),
+        PropertyKind::List { count, item } => {
+            for _ in 0..values.read(count)? as usize {
+                values.read(item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_vertices<V: ValueReader>(
+    element: &Element,
+    values: &mut V,
+    data: &mut PlyData,
+) -> Result<()> {
+    // Indices into the row of scalar properties
+    let scalars = element
+        .properties
+        .iter()
+        .filter_map(|p| match p.kind {
+            PropertyKind::Scalar(ty) => Some((p.name.as_str(), ty)),
+            PropertyKind::List { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    let find = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| scalars.iter().position(|(n, _)| n == name))
+    };
+    let find_all = |names: &[&[&str]]| names.iter().map(|n| find(n)).collect::<Option<Vec<_>>>();
+
+    let position =
+        find_all(&[&["x"], &["y"], &["z"]]).ok_or(PlyError::MissingProperty("x, y, z"))?;
+    let normal = find_all(&[&["nx"], &["ny"], &["nz"]]);
+    let texture_coord = find_all(&[
+        &["u", "s", "texture_u", "texture_s"],
+        &["v", "t", "texture_v", "texture_t"],
+    ]);
+    let color = find_all(&[
+        &["red", "diffuse_red"],
+        &["green", "diffuse_green"],
+        &["blue", "diffuse_blue"],
+    ]);
+
+    let mut row = Vec::with_capacity(scalars.len());
+    for _ in 0..element.count {
+        row.clear();
+        for property in &element.properties {
+            read_property(&property.kind, values, &mut row)?;
+        }
+        let get = |ids: &[usize]| [0, 1, 2].map(|i| row[ids[i % ids.len()]] as f32);
+        let [x, y, z] = get(&position);
+        data.positions.push(Point3::new(x, y, z));
+        if let Some(normal) = &normal {
+            let [x, y, z] = get(normal);
+            data.normals.push(Dir3::new(x, y, z));
+        }
+        if let Some(texture_coord) = &texture_coord {
+            let [u, v, _] = get(texture_coord);
+            // Images are stored top down
+            data.texture_coords.push(Vec2f::new(u, 1.0 - v));
+        }
+        if let Some(color) = &color {
+            data.colors
+                .push(scalars[color[0]].1.decode_color(get(color)));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces<V: ValueReader>(element: &Element, values: &mut V, data: &mut PlyData) -> Result<()> {
+    let mut indices = Vec::new();
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::List { count, item }
+                    if property.name == "vertex_indices" || property.name == "vertex_index" =>
+                {
+                    indices.clear();
+                    for _ in 0..values.read(count)? as usize {
+                        indices.push(values.read(item)? as usize);
+                    }
+                }
+                ref kind => read_property(kind, values, &mut Vec::new())?,
+            }
+        }
+        if let Some(&index) = indices.iter().find(|&&i| i >= data.positions.len()) {
+            return Err(PlyError::IndexOutOfRange(index));
+        }
+        if indices.len() < 3 {
+            continue;
+        }
+        let points = indices
+            .iter()
+            .map(|&i| data.positions[i])
+            .collect::<Vec<_>>();
+        for [a, b, c] in triangulate(&points) {
+            data.faces
+                .push([indices[a], indices[b], indices[c]].map(|i| i as u32));
+        }
+    }
+    Ok(())
+}
+
+impl PlyData {
+    // Missing normals are averaged over the faces sharing the vertex, weighted by their area.
+    fn finish(self) -> PlyMesh {
+        let mut normals = self.normals;
+        if normals.is_empty() {
+            normals = vec![Dir3::ZERO; self.positions.len()];
+            for face in &self.faces {
+                let [p0, p1, p2] = face.map(|i| self.positions[i as usize]);
+                let normal = Dir3::cross(p1 - p0, p2 - p0);
+                for &i in face {
+                    normals[i as usize] += normal;
+                }
+            }
+        }
+        for normal in &mut normals {
+            *normal = normalize_or(*normal, Dir3::UP);
+        }
+
+        PlyMesh {
+            geometry: MeshGeometry {
+                positions: self.positions,
+                normals,
+                texture_coords: self.texture_coords,
+                colors: Vec::new(),
+                indices: self.faces,
+            },
+            vertex_colors: (!self.colors.is_empty()).then_some(self.colors),
+        }
+    }
+}
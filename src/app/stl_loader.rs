@@ -0,0 +1,126 @@
+use std::io::Read;
+
+use ray_tracing_in_a_weekend::{triangle_geometry::TriangleGeometry, Dir3, Point3, Vec2f};
+
+use crate::obj_loader::normalize_or;
+
+// Reads ASCII and binary files. STL has no shared vertices, so every triangle gets its facet
+// normal, or the normal by the winding order if the stored one is zero.
+pub fn load_stl_mesh<R: Read>(mut reader: R) -> Result<Vec<TriangleGeometry>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(StlError::IoError)?;
+    // Binary files may start with "solid" as well, but their size is determined by the
+    // triangle count after the 80 byte header.
+    let binary_size = bytes
+        .get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    if binary_size == Some(bytes.len()) || !bytes.trim_ascii_start().starts_with(b"solid") {
+        load_binary(&bytes)
+    } else {
+        let text = std::str::from_utf8(&bytes).map_err(|_| StlError::Syntax(0, "not UTF-8"))?;
+        load_ascii(text)
+    }
+}
+
+#[derive(Debug)]
+pub enum StlError {
+    IoError(std::io::Error),
+    // The line number starting at one and the problem, zero if the line is unknown
+    Syntax(usize, &'static str),
+    UnexpectedEnd,
+}
+
+impl std::fmt::Display for StlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StlError::IoError(err) => write!(f, "{err}"),
+            StlError::Syntax(line, message) => write!(f, "line {line}: {message}"),
+            StlError::UnexpectedEnd => write!(f, "unexpected end of file"),
+        }
+    }
+}
+
+impl std::error::Error for StlError {}
+
+type Result<T> = std::result::Result<T, StlError>;
+
+fn new_triangle(normal: Dir3, positions: [Point3; 3]) -> TriangleGeometry {
+    let [p0, p1, p2] = positions;
+    let normal = normalize_or(
+        normal,
+        normalize_or(Dir3::cross(p1 - p0, p2 - p0), Dir3::UP),
+    );
+    TriangleGeometry::new(positions, [normal; 3], [Vec2f::ZERO; 3])
+}
+
+// An 80 byte header, the triangle count and per triangle the normal, the three vertices and
+// two attribute bytes, all little endian.
+fn load_binary(bytes: &[u8]) -> Result<Vec<TriangleGeometry>> {
+    let count = bytes.get(80..84).ok_or(StlError::UnexpectedEnd)?;
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    let data = bytes
+        .get(84..84 + 50 * count)
+        .ok_or(StlError::UnexpectedEnd)?;
+    let triangles = data
+        .chunks_exact(50)
+        .map(|record| {
+            let v = |i: usize| {
+                let f = |j: usize| {
+                    let start = 12 * i + 4 * j;
+                    f32::from_le_bytes(record[start..start + 4].try_into().unwrap())
+                };
+                [f(0), f(1), f(2)]
+            };
+            new_triangle(
+                Dir3::new_from_arr(v(0)),
+                [1, 2, 3].map(|i| Point3::new_from_arr(v(i))),
+            )
+        })
+        .collect();
+    Ok(triangles)
+}
+
+// solid name, then facet normal nx ny nz / outer loop / vertex x y z (three times) / endloop /
+// endfacet per triangle and finally endsolid. Several solids are read one after the other.
+fn load_ascii(text: &str) -> Result<Vec<TriangleGeometry>> {
+    let mut triangles = Vec::new();
+    let mut normal = Dir3::ZERO;
+    let mut vertices = Vec::with_capacity(3);
+    for (line_id, line) in text.lines().enumerate() {
+        let error = |message| StlError::Syntax(line_id + 1, message);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("facet") => {
+                if tokens.next() != Some("normal") {
+                    return Err(error("expected facet normal"));
+                }
+                normal = Dir3::new_from_arr(take_xyz(&mut tokens, line_id)?);
+                vertices.clear();
+            }
+            Some("vertex") => {
+                vertices.push(Point3::new_from_arr(take_xyz(&mut tokens, line_id)?));
+            }
+            Some("endfacet") => {
+                let Ok(positions) = <[Point3; 3]>::try_from(vertices.as_slice()) else {
+                    return Err(error("facet without three vertices"));
+                };
+                triangles.push(new_triangle(normal, positions));
+            }
+            Some("solid" | "outer" | "endloop" | "endsolid") | None => {}
+            Some(_) => return Err(error("unexpected statement")),
+        }
+    }
+    Ok(triangles)
+}
+
+fn take_xyz<'a>(tokens: &mut impl Iterator<Item = &'a str>, line_id: usize) -> Result<[f32; 3]> {
+    let mut xyz = [0.0; 3];
+    for v in &mut xyz {
+        *v = tokens
+            .next()
+            .ok_or(StlError::Syntax(line_id + 1, "missing value"))?
+            .parse::<f32>()
+            .map_err(|_| StlError::Syntax(line_id + 1, "invalid number"))?;
+    }
+    Ok(xyz)
+}
@@ -1,7 +1,7 @@
 use rand::Rng;
 use ray_tracing_in_a_weekend::*;
 
-use super::world_builder::{NodeRef, WorldBuilder};
+use super::world_builder::{NodeBuilder, NodeRef, WorldBuilder};
 
 pub fn create_world_suzanne<'a>(wb: &'a WorldBuilder<'a>, _rng: &'a mut common::TRng) -> World<'a> {
     let camera = Camera::build()
@@ -14,10 +14,13 @@ pub fn create_world_suzanne<'a>(wb: &'a WorldBuilder<'a>, _rng: &'a mut common::
     let mat_monkey = wb.material_lambert_solid(Color::new_rgb(1.0, 0.2, 0.2));
     let scene = wb
         .new_group()
-        .add(wb.new_mesh_from_file_obj_uniform_material(
-            std::path::Path::new("input/suzanne.obj"),
-            mat_monkey,
-        ))
+        .add(
+            wb.new_mesh_from_file_obj_uniform_material(
+                std::path::Path::new("input/suzanne.obj"),
+                mat_monkey,
+            )
+            .unwrap(),
+        )
         .add(
             wb.new_obj_sphere(1000.1, mat_ground)
                 .translate(Dir3::new(0.0, -1000.0, 0.0)),
@@ -27,6 +30,91 @@ pub fn create_world_suzanne<'a>(wb: &'a WorldBuilder<'a>, _rng: &'a mut common::
     scene.finish(wb, background, camera)
 }
 
+pub fn create_world_suzanne_instances<'a>(
+    wb: &'a WorldBuilder<'a>,
+    rng: &'a mut common::TRng,
+) -> World<'a> {
+    // A grid of 100 monkeys, all sharing the same mesh
+    let camera = Camera::build()
+        .vertical_fov(40.0, 3.0 / 4.0)
+        .position(Point3::new(0.0, 12.0, 30.0))
+        .look_at(Dir3::UP, Point3::new(0.0, 0.0, 0.0))
+        .build();
+    let background = BackgroundColor::Sky;
+    let mat_ground = wb.material_lambert_solid(Color::new_rgb(0.4, 0.4, 0.4));
+    let mat_monkey = wb.material_lambert_solid(Color::new_rgb(1.0, 0.2, 0.2));
+    let monkey = wb
+        .new_mesh_from_file_obj_uniform_material(
+            std::path::Path::new("input/suzanne.obj"),
+            mat_monkey,
+        )
+        .unwrap()
+        .build();
+    let mut scene = wb.new_group().add(
+        wb.new_obj_sphere(1000.1, mat_ground)
+            .translate(Dir3::new(0.0, -1000.0, 0.0)),
+    );
+    for x in -5..5 {
+        for z in -5..5 {
+            scene = scene.add(
+                wb.new_group()
+                    .add(&monkey)
+                    .rotate_around_up(rng.gen_range(-45.0..45.0))
+                    .translate(Dir3::new(3.0 * x as f32 + 1.5, 0.0, 3.0 * z as f32 + 1.5)),
+            );
+        }
+    }
+
+    scene.build().finish(wb, background, camera)
+}
+
+pub fn create_world_transformations<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // A squashed sphere, a tilted box and a monkey looking at the camera
+    let camera_position = Point3::new(0.0, 3.0, 10.0);
+    let camera = Camera::build()
+        .vertical_fov(40.0, 9.0 / 16.0)
+        .position(camera_position)
+        .look_at(Dir3::UP, Point3::new(0.0, 1.0, 0.0))
+        .build();
+    let background = BackgroundColor::Sky;
+    let mat_ground = wb.material_lambert_solid(Color::new_rgb(0.4, 0.4, 0.4));
+    let mat_red = wb.material_lambert_solid(Color::new_rgb(0.8, 0.2, 0.2));
+    let mat_blue = wb.material_lambert_solid(Color::new_rgb(0.2, 0.2, 0.8));
+    let mat_metal = wb.material_metal_solid(Color::new_rgb(0.8, 0.8, 0.8), 0.1);
+
+    let scene = wb
+        .new_group()
+        .add(wb.new_obj_sphere_ground(1000.0, 0.0, mat_ground))
+        .add(
+            wb.new_obj_sphere(1.0, mat_metal)
+                .scale(Dir3::new(1.5, 0.5, 1.0))
+                .rotate_axis(Dir3::BACKWARD, 20.0)
+                .translate(Dir3::new(-3.0, 1.0, 0.0)),
+        )
+        .add(
+            wb.new_obj_box(1.5, 1.5, 1.5, mat_blue)
+                .translate(Dir3::new(-0.75, -0.75, -0.75))
+                .rotate_axis(Dir3::new(1.0, 1.0, 0.0), 45.0)
+                .translate(Dir3::new(3.0, 1.3, 0.0)),
+        )
+        .add(
+            wb.new_mesh_from_file_obj_uniform_material(
+                std::path::Path::new("input/suzanne.obj"),
+                mat_red,
+            )
+            .unwrap()
+            .scale_uniform(0.8)
+            .translate(Dir3::new(0.0, 1.0, -1.0))
+            .look_at(camera_position, Dir3::UP),
+        )
+        .build();
+
+    scene.finish(wb, background, camera)
+}
+
 pub fn create_world_final_scene2<'a>(
     wb: &'a WorldBuilder<'a>,
     rng: &'a mut common::TRng,
@@ -323,6 +411,591 @@ pub fn create_world_simple_plane<'a>(
     scene.finish(wb, background, camera)
 }
 
+pub fn create_world_two_lamps<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Two lamps of different strength above a floor, one of them tilted
+    let camera = Camera::build()
+        .vertical_fov(60.0, 9.0 / 16.0)
+        .position(Point3::new(0.0, 6.0, 10.0))
+        .look_at(Dir3::UP, Point3::ORIGIN)
+        .build();
+    let background = BackgroundColor::Solid {
+        color: Color::BLACK,
+    };
+
+    let mat_warm = wb.material_diffuse_light_solid(Color::new_rgb(40.0, 30.0, 20.0));
+    let mat_cold = wb.material_diffuse_light_solid(Color::new_rgb(5.0, 7.5, 10.0));
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.7, 0.7, 0.7));
+    let mat_sphere = wb.material_lambert_solid(Color::new_rgb(0.7, 0.3, 0.3));
+
+    let scene = wb
+        .new_group()
+        .add(
+            wb.new_obj_rect_xz(Point3::ORIGIN, 1.0, 1.0, mat_warm)
+                .set_all_geo_as_poi()
+                .translate(Dir3::new(-3.0, 4.0, 0.0)),
+        )
+        .add(
+            wb.new_obj_rect_xz(Point3::ORIGIN, 2.0, 2.0, mat_cold)
+                .set_all_geo_as_poi()
+                .rotate_axis(Dir3::BACKWARD, -30.0)
+                .translate(Dir3::new(3.0, 3.0, 0.0)),
+        )
+        .add(
+            wb.new_obj_sphere(1.0, mat_sphere)
+                .translate(Dir3::new(0.0, 1.0, 0.0)),
+        )
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 20.0, 20.0, mat_floor))
+        .build();
+
+    scene.finish(wb, background, camera)
+}
+
+pub fn create_world_shape_lamps<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // A glowing sphere and a glowing box lighting a floor
+    let camera = Camera::build()
+        .vertical_fov(60.0, 9.0 / 16.0)
+        .position(Point3::new(0.0, 6.0, 10.0))
+        .look_at(Dir3::UP, Point3::ORIGIN)
+        .build();
+    let background = BackgroundColor::Solid {
+        color: Color::BLACK,
+    };
+
+    let mat_sphere_lamp = wb.material_diffuse_light_solid(Color::new_rgb(20.0, 15.0, 10.0));
+    let mat_box_lamp = wb.material_diffuse_light_solid(Color::new_rgb(2.0, 3.0, 4.0));
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.7, 0.7, 0.7));
+    let mat_sphere = wb.material_lambert_solid(Color::new_rgb(0.7, 0.3, 0.3));
+
+    let scene = wb
+        .new_group()
+        .add(
+            wb.new_obj_sphere(0.5, mat_sphere_lamp)
+                .set_all_geo_as_poi()
+                .translate(Dir3::new(-3.0, 3.0, 0.0)),
+        )
+        .add(
+            wb.new_obj_box(1.0, 1.0, 1.0, mat_box_lamp)
+                .set_all_geo_as_poi()
+                .rotate_around_up(30.0)
+                .translate(Dir3::new(3.0, 0.0, 0.0)),
+        )
+        .add(
+            wb.new_obj_sphere(1.0, mat_sphere)
+                .translate(Dir3::new(0.0, 1.0, 0.0)),
+        )
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 20.0, 20.0, mat_floor))
+        .build();
+
+    scene.finish(wb, background, camera)
+}
+
+// The stage of the material demos: a grey floor under a rect lamp, seen from the front
+fn showcase_stage<'a>(
+    wb: &'a WorldBuilder<'a>,
+    camera_position: Point3,
+    camera_target: Point3,
+    mat_floor: &'a Material<'a>,
+) -> (Camera, NodeBuilder<'a>) {
+    let camera = Camera::build()
+        .vertical_fov(40.0, 9.0 / 16.0)
+        .position(camera_position)
+        .look_at(Dir3::UP, camera_target)
+        .build();
+    let mat_lamp = wb.material_diffuse_light_solid(Color::new_rgb(15.0, 15.0, 15.0));
+    let stage = wb
+        .new_group()
+        .add(
+            wb.new_obj_rect_xz(Point3::ORIGIN, 4.0, 2.0, mat_lamp)
+                .set_all_geo_as_poi()
+                .rotate_axis(Dir3::RIGHT, 180.0)
+                .translate(Dir3::new(0.0, 6.0, 2.0)),
+        )
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 30.0, 30.0, mat_floor));
+    (camera, stage)
+}
+
+pub fn create_world_conductors<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // The metal presets from left to right, getting rougher from back to front
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let (camera, mut scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 5.0, 10.0),
+        Point3::new(0.0, 0.5, 0.0),
+        mat_floor,
+    );
+
+    let presets = [
+        ConductorPreset::Gold,
+        ConductorPreset::Copper,
+        ConductorPreset::Aluminium,
+        ConductorPreset::Silver,
+    ];
+    for (x, preset) in presets.into_iter().enumerate() {
+        for (z, roughness) in [0.0, 0.2, 0.5].into_iter().enumerate() {
+            scene = scene.add(
+                wb.new_obj_sphere(0.5, wb.material_conductor_preset(preset, roughness))
+                    .translate(Dir3::new(1.5 * x as f32 - 2.25, 0.5, 1.5 * z as f32 - 1.5)),
+            );
+        }
+    }
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+pub fn create_world_rough_glass<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Glass getting rougher from left to right in front of a checkered wall, tinted in the back
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let (camera, scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 3.0, 8.0),
+        Point3::new(0.0, 0.7, 0.0),
+        mat_floor,
+    );
+    let checker = wb.texture_checker(
+        3.0,
+        wb.texture_solid(Color::new_rgb(0.8, 0.8, 0.8)),
+        wb.texture_solid(Color::new_rgb(0.1, 0.1, 0.1)),
+    );
+    let mat_checker = wb.material_lambert(checker);
+    let tint = wb.texture_solid(Color::new_rgb(0.6, 0.8, 1.0));
+
+    let mut scene = scene.add(
+        wb.new_obj_rect_xy(Point3::ORIGIN, 30.0, 10.0, mat_checker)
+            .translate(Dir3::new(0.0, 5.0, -3.0)),
+    );
+    for (x, roughness) in [0.0, 0.1, 0.3, 0.6].into_iter().enumerate() {
+        let white = wb.texture_solid(Color::WHITE);
+        let clear = wb.material_rough_dielectric(1.5, white, roughness, Color::BLACK);
+        let tinted = wb.material_rough_dielectric(1.5, tint, roughness, Color::BLACK);
+        let x = 1.5 * x as f32 - 2.25;
+        scene = scene
+            .add(
+                wb.new_obj_sphere(0.6, clear)
+                    .translate(Dir3::new(x, 0.6, 0.5)),
+            )
+            .add(
+                wb.new_obj_box(1.0, 1.4, 0.3, tinted)
+                    .translate(Dir3::new(x, 0.7, -1.5)),
+            );
+    }
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+pub fn create_world_absorbing_glass<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Spheres of the same green glass get darker with their size, next to a pool of water
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let (camera, scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 3.0, 8.0),
+        Point3::new(0.0, 0.7, 0.0),
+        mat_floor,
+    );
+    let green = absorption_from_color_at_distance(Color::new_rgb(0.4, 0.9, 0.5), 1.0);
+    let mat_glass = wb.material_dielectric_absorbing(1.5, green);
+    let water = absorption_from_color_at_distance(Color::new_rgb(0.6, 0.85, 0.9), 1.0);
+    let mat_water = wb.material_dielectric_absorbing(1.33, water);
+
+    let mut scene = scene.add(
+        wb.new_obj_box(1.4, 1.0, 1.4, mat_water)
+            .translate(Dir3::new(1.1, 0.5, -0.5)),
+    );
+    for (x, radius) in [(-2.3, 0.2), (-1.6, 0.4), (-0.5, 0.6)] {
+        scene = scene.add(
+            wb.new_obj_sphere(radius, mat_glass)
+                .translate(Dir3::new(x, radius, 0.5)),
+        );
+    }
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+pub fn create_world_coated<'a>(wb: &'a WorldBuilder<'a>, rng: &'a mut common::TRng) -> World<'a> {
+    // Coats over diffuse, metal and textured bases in the back row, the bases alone in front
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let (camera, mut scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 4.0, 8.0),
+        Point3::new(0.0, 0.5, 0.0),
+        mat_floor,
+    );
+    let bases = [
+        wb.material_lambert_solid(Color::new_rgb(0.7, 0.05, 0.05)),
+        wb.material_lambert_solid(Color::new_rgb(0.05, 0.2, 0.6)),
+        wb.material_conductor_preset(ConductorPreset::Copper, 0.4),
+        wb.material_lambert(wb.texture_marble(4.0, rng)),
+    ];
+    for (x, (base, roughness)) in bases.into_iter().zip([0.0, 0.1, 0.0, 0.3]).enumerate() {
+        let x = 1.5 * x as f32 - 2.25;
+        let coated = wb.material_coated(base, 1.5, roughness);
+        scene = scene
+            .add(
+                wb.new_obj_sphere(0.6, base)
+                    .translate(Dir3::new(x, 0.6, 1.0)),
+            )
+            .add(
+                wb.new_obj_sphere(0.6, coated)
+                    .translate(Dir3::new(x, 0.6, -0.6)),
+            );
+    }
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+pub fn create_world_principled<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Roughness increasing to the right for a dielectric in the back row and a metal in the
+    // middle one. In front sheen, clearcoat, specular tint and smooth and rough transmission.
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let (camera, mut scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 5.0, 9.0),
+        Point3::new(0.0, 0.5, 0.0),
+        mat_floor,
+    );
+    let tex_red = wb.texture_solid(Color::new_rgb(0.7, 0.1, 0.1));
+    let tex_gold = wb.texture_solid(Color::new_rgb(1.0, 0.78, 0.34));
+    let tex_glass = wb.texture_solid(Color::new_rgb(0.8, 0.9, 1.0));
+
+    for x in 0..5 {
+        let roughness = wb.texture_scalar(0.05 + 0.2 * x as f32);
+        let dielectric = wb.material_principled(Principled {
+            roughness,
+            ..wb.principled(tex_red)
+        });
+        let metal = wb.material_principled(Principled {
+            roughness,
+            metallic: wb.texture_scalar(1.0),
+            ..wb.principled(tex_gold)
+        });
+        let x = 1.4 * x as f32 - 2.8;
+        scene = scene
+            .add(
+                wb.new_obj_sphere(0.6, dielectric)
+                    .translate(Dir3::new(x, 0.6, -2.0)),
+            )
+            .add(
+                wb.new_obj_sphere(0.6, metal)
+                    .translate(Dir3::new(x, 0.6, -0.5)),
+            );
+    }
+    let specials = [
+        Principled {
+            roughness: wb.texture_scalar(1.0),
+            sheen: wb.texture_scalar(1.0),
+            ..wb.principled(tex_red)
+        },
+        Principled {
+            roughness: wb.texture_scalar(0.8),
+            clearcoat: wb.texture_scalar(1.0),
+            ..wb.principled(tex_red)
+        },
+        Principled {
+            roughness: wb.texture_scalar(0.2),
+            specular: wb.texture_scalar(1.0),
+            specular_tint: wb.texture_scalar(1.0),
+            ..wb.principled(tex_red)
+        },
+        Principled {
+            roughness: wb.texture_scalar(0.0),
+            transmission: wb.texture_scalar(1.0),
+            ..wb.principled(tex_glass)
+        },
+        Principled {
+            roughness: wb.texture_scalar(0.3),
+            transmission: wb.texture_scalar(1.0),
+            ..wb.principled(tex_glass)
+        },
+    ];
+    for (x, principled) in specials.into_iter().enumerate() {
+        let x = 1.4 * x as f32 - 2.8;
+        let material = wb.material_principled(principled);
+        scene = scene.add(
+            wb.new_obj_sphere(0.6, material)
+                .translate(Dir3::new(x, 0.6, 1.0)),
+        );
+    }
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+pub fn create_world_oren_nayar<'a>(
+    wb: &'a WorldBuilder<'a>,
+    _rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Clay spheres getting rougher to the right, lit from behind the camera where rough
+    // surfaces look flat
+    let camera = Camera::build()
+        .vertical_fov(45.0, 9.0 / 16.0)
+        .position(Point3::new(0.0, 2.0, 9.0))
+        .look_at(Dir3::UP, Point3::new(0.0, 0.7, 0.0))
+        .build();
+    let background = BackgroundColor::Solid {
+        color: Color::new_rgb(0.05, 0.05, 0.05),
+    };
+
+    let mat_lamp = wb.material_diffuse_light_solid(Color::new_rgb(8.0, 8.0, 8.0));
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let clay = Color::new_rgb(0.7, 0.4, 0.25);
+
+    let mut scene = wb
+        .new_group()
+        .add(
+            wb.new_obj_rect_xz(Point3::ORIGIN, 4.0, 2.0, mat_lamp)
+                .set_all_geo_as_poi()
+                .rotate_axis(Dir3::RIGHT, -90.0)
+                .translate(Dir3::new(0.0, 3.0, 11.0)),
+        )
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 30.0, 30.0, mat_floor));
+    for (x, sigma) in [0.0, 0.25, 0.5, 1.0].into_iter().enumerate() {
+        let x = 1.5 * x as f32 - 2.25;
+        let material = wb.material_oren_nayar_solid(clay, sigma);
+        scene = scene.add(
+            wb.new_obj_sphere(0.65, material)
+                .translate(Dir3::new(x, 0.65, 0.0)),
+        );
+    }
+
+    scene.build().finish(wb, background, camera)
+}
+
+// A tangent space normal map of round dimples in a square grid
+fn dimples_normal_map(cells: u32, cell_size: u32) -> image::RgbaImage {
+    let size = cells * cell_size;
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        // Offset from the center of the cell, with y pointing up in the image
+        let half = 0.5 * cell_size as f32;
+        let dx = (x % cell_size) as f32 + 0.5 - half;
+        let dy = half - ((y % cell_size) as f32 + 0.5);
+        let radius = 0.8 * half;
+        let d2 = dx * dx + dy * dy;
+        // The dimple is a shallow spherical cap, its normal points to the center of the sphere
+        let sphere_radius = 2.0 * radius;
+        let normal = if d2 < radius * radius {
+            Dir3::new(-dx, -dy, (sphere_radius * sphere_radius - d2).sqrt()).unit()
+        } else {
+            Dir3::new(0.0, 0.0, 1.0)
+        };
+        let [r, g, b] = normal.0.e.map(|c| ((c * 0.5 + 0.5) * 255.0).round() as u8);
+        image::Rgba([r, g, b, 255])
+    })
+}
+
+pub fn create_world_bump_mapping<'a>(
+    wb: &'a WorldBuilder<'a>,
+    rng: &'a mut common::TRng,
+) -> World<'a> {
+    // Marble bumps on diffuse and copper spheres, dimples from a normal map on the plastic
+    // sphere and the floor
+    let tex_marble = wb.texture_marble(4.0, rng);
+    let tex_dimples = wb.texture_image(dimples_normal_map(16, 64));
+    let mat_floor = wb.material_normal_mapped(
+        wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5)),
+        tex_dimples,
+        1.0,
+    );
+    let mat_clay = wb.material_bump_mapped(
+        wb.material_lambert_solid(Color::new_rgb(0.7, 0.4, 0.25)),
+        tex_marble,
+        0.03,
+    );
+    let mat_copper = wb.material_bump_mapped(
+        wb.material_conductor_preset(ConductorPreset::Copper, 0.2),
+        tex_marble,
+        0.01,
+    );
+    let mat_plastic = wb.material_normal_mapped(
+        wb.material_principled(Principled {
+            roughness: wb.texture_scalar(0.3),
+            ..wb.principled(wb.texture_solid(Color::new_rgb(0.1, 0.2, 0.6)))
+        }),
+        wb.texture_image(dimples_normal_map(8, 32)),
+        1.0,
+    );
+
+    let (camera, scene) = showcase_stage(
+        wb,
+        Point3::new(0.0, 3.0, 8.0),
+        Point3::new(0.0, 0.7, 0.0),
+        mat_floor,
+    );
+    let scene = scene
+        .add(
+            wb.new_obj_sphere(0.8, mat_clay)
+                .translate(Dir3::new(-2.0, 0.8, 0.0)),
+        )
+        .add(
+            wb.new_obj_sphere(0.8, mat_copper)
+                .translate(Dir3::new(0.0, 0.8, 0.0)),
+        )
+        .add(
+            wb.new_obj_sphere(0.8, mat_plastic)
+                .translate(Dir3::new(2.0, 0.8, 0.0)),
+        );
+
+    scene.build().finish(wb, BackgroundColor::Sky, camera)
+}
+
+// A diamond mesh of wires, transparent between them
+fn fence_image(size: u32, cells: u32) -> image::RgbaImage {
+    let cell = size as f32 / cells as f32;
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        let on_wire = |d: f32| ((d / cell).fract() - 0.5).abs() > 0.42;
+        let alpha = if on_wire(x + y) || on_wire(x - y + size as f32) {
+            255
+        } else {
+            0
+        };
+        image::Rgba([180, 180, 170, alpha])
+    })
+}
+
+// A green leaf with a lighter vein along its middle, transparent around it
+fn leaf_image(size: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let u = (x as f32 + 0.5) / size as f32;
+        let v = (y as f32 + 0.5) / size as f32;
+        // Widest at a third of its length
+        let half_width = 0.45 * (std::f32::consts::PI * v.powf(0.7)).sin();
+        let offset = (u - 0.5).abs();
+        if offset > half_width {
+            image::Rgba([0, 0, 0, 0])
+        } else if offset < 0.01 {
+            image::Rgba([150, 190, 80, 255])
+        } else {
+            image::Rgba([40, 110, 30, 255])
+        }
+    })
+}
+
+pub fn create_world_cutout<'a>(wb: &'a WorldBuilder<'a>, _rng: &'a mut common::TRng) -> World<'a> {
+    // Spheres behind a wire fence, leaves lying around and a ghost sphere which is hit with a
+    // probability of one third
+    let camera = Camera::build()
+        .vertical_fov(40.0, 9.0 / 16.0)
+        .position(Point3::new(0.0, 2.5, 8.0))
+        .look_at(Dir3::UP, Point3::new(0.0, 0.8, 0.0))
+        .build();
+    let background = BackgroundColor::Sky;
+
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let mat_red = wb.material_lambert_solid(Color::new_rgb(0.7, 0.1, 0.1));
+    let mat_gold = wb.material_conductor_preset(ConductorPreset::Gold, 0.2);
+    let tex_fence = wb.texture_image(fence_image(1024, 24));
+    let (eta, k) = ConductorPreset::Aluminium.complex_ior();
+    let mat_fence = wb.material_cutout(
+        wb.material_conductor(tex_fence, eta, k, 0.5),
+        wb.texture_channel(tex_fence, 3),
+        Some(0.5),
+    );
+    let tex_leaf = wb.texture_image(leaf_image(256));
+    let mat_leaf = wb.material_cutout(
+        wb.material_lambert(tex_leaf),
+        wb.texture_channel(tex_leaf, 3),
+        Some(0.5),
+    );
+    let mat_ghost = wb.material_cutout(
+        wb.material_lambert_solid(Color::new_rgb(0.2, 0.3, 0.8)),
+        wb.texture_scalar(1.0 / 3.0),
+        None,
+    );
+
+    let mut scene = wb
+        .new_group()
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 30.0, 30.0, mat_floor))
+        .add(
+            wb.new_obj_sphere(0.8, mat_red)
+                .translate(Dir3::new(-1.6, 0.8, -1.0)),
+        )
+        .add(
+            wb.new_obj_sphere(0.8, mat_gold)
+                .translate(Dir3::new(1.6, 0.8, -1.0)),
+        )
+        .add(
+            wb.new_obj_sphere(0.8, mat_ghost)
+                .translate(Dir3::new(0.0, 0.8, -2.5)),
+        )
+        .add(
+            wb.new_obj_rect_xy(Point3::ORIGIN, 6.0, 1.2, mat_fence)
+                .translate(Dir3::new(0.0, 0.6, 0.5)),
+        );
+    let leaves = [
+        (-2.0, 1.5, 20.0),
+        (-0.8, 2.2, 130.0),
+        (0.9, 1.8, 250.0),
+        (2.1, 2.4, 75.0),
+    ];
+    for (x, z, angle) in leaves {
+        scene = scene.add(
+            wb.new_obj_rect_xz(Point3::ORIGIN, 0.6, 1.0, mat_leaf)
+                .rotate_axis(Dir3::UP, angle)
+                .translate(Dir3::new(x, 0.01, z)),
+        );
+    }
+
+    scene.build().finish(wb, background, camera)
+}
+
+pub fn create_world_mix<'a>(wb: &'a WorldBuilder<'a>, rng: &'a mut common::TRng) -> World<'a> {
+    // From the left: rust patches on steel, half gold and half red paint, frosted patches in
+    // glass and stone with glowing veins
+    let camera = Camera::build()
+        .vertical_fov(40.0, 9.0 / 16.0)
+        .position(Point3::new(0.0, 2.0, 9.0))
+        .look_at(Dir3::UP, Point3::new(0.0, 0.7, 0.0))
+        .build();
+    let background = BackgroundColor::Sky;
+
+    let mat_floor = wb.material_lambert_solid(Color::new_rgb(0.5, 0.5, 0.5));
+    let tex_noise = wb.texture_marble(3.0, rng);
+    let (eta, k) = ConductorPreset::Silver.complex_ior();
+    let steel = wb.material_conductor(wb.texture_solid(Color::new_rgb(0.6, 0.6, 0.6)), eta, k, 0.2);
+    let rust = wb.material_oren_nayar_solid(Color::new_rgb(0.45, 0.18, 0.06), 0.5);
+    let paint = wb.material_lambert_solid(Color::new_rgb(0.7, 0.1, 0.1));
+    let gold = wb.material_conductor_preset(ConductorPreset::Gold, 0.1);
+    let glass = wb.material_dielectric(1.5);
+    let frost = wb.material_lambert_solid(Color::new_rgb(0.9, 0.9, 0.9));
+    let stone = wb.material_lambert_solid(Color::new_rgb(0.1, 0.1, 0.12));
+    let glow = wb.material_diffuse_light_solid(Color::new_rgb(4.0, 1.5, 0.3));
+    let materials = [
+        wb.material_mix(steel, rust, tex_noise),
+        wb.material_mix(gold, paint, wb.texture_scalar(0.5)),
+        wb.material_mix(glass, frost, tex_noise),
+        wb.material_mix(stone, glow, tex_noise),
+    ];
+
+    let mut scene = wb
+        .new_group()
+        .add(wb.new_obj_rect_xz(Point3::ORIGIN, 30.0, 30.0, mat_floor));
+    for (x, material) in materials.into_iter().enumerate() {
+        let x = 1.5 * x as f32 - 2.25;
+        scene = scene.add(
+            wb.new_obj_sphere(0.65, material)
+                .translate(Dir3::new(x, 0.65, 0.0)),
+        );
+    }
+
+    scene.build().finish(wb, background, camera)
+}
+
 pub fn create_world_earth_mapped<'a>(
     wb: &'a WorldBuilder<'a>,
     _rng: &'a mut common::TRng,
@@ -1,2 +1,3 @@
 pub mod demo_worlds;
+pub mod scene_file;
 pub mod world_builder;
@@ -0,0 +1,952 @@
+use ray_tracing_in_a_weekend::*;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+
+use super::world_builder::{NodeBuilder, NodeRef, WorldBuilder};
+
+// Scenes described in TOML, see input/cornell_box.toml for an example. Textures, materials and
+// nodes are named tables which can be referenced by their name. Every object holds at most one
+// of sphere, box, rect, mesh or node, where all objects referencing the same node share its
+// geometry. Meshes are OBJ, glTF, PLY or STL files picked by the extension. Without a material
+// OBJ meshes use the materials of their MTL libraries, glTF meshes always use their own and PLY
+// meshes their vertex colors. File paths are relative to the scene file.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraDesc,
+    #[serde(default)]
+    background: BackgroundDesc,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    nodes: HashMap<String, Spanned<ObjectDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    position: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    vertical_fov: f32,
+    // Height divided by width
+    #[serde(default = "default_aspect_ratio")]
+    aspect_ratio: f32,
+    #[serde(default)]
+    aperture: f32,
+    // Defaults to the distance of look_at
+    focus_distance: Option<f32>,
+    motion_blur: Option<[f32; 2]>,
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_aspect_ratio() -> f32 {
+    1.0
+}
+
+fn default_coat_index_of_refraction() -> f32 {
+    1.5
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum BackgroundDesc {
+    #[default]
+    Sky,
+    Solid([f32; 3]),
+}
+
+// Either a solid color or the name of a texture
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Color([f32; 3]),
+    Name(String),
+}
+
+// Either a number or the name of a texture, whose red channel is used
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarRef {
+    Value(f32),
+    Name(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f32; 3],
+    },
+    // linear is true for data like normal or height maps, colors are sRGB encoded otherwise
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        linear: bool,
+    },
+    Marble {
+        scale: f32,
+    },
+    Checker {
+        inv_frequency: f32,
+        even: TextureRef,
+        odd: TextureRef,
+    },
+    // One channel of a packed texture as gray, 0 is red and 3 alpha
+    Channel {
+        texture: TextureRef,
+        channel: usize,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambert {
+        albedo: TextureRef,
+    },
+    // Rough diffuse, sigma is the standard deviation of the facet slopes in radians
+    OrenNayar {
+        albedo: TextureRef,
+        sigma: ScalarRef,
+    },
+    Metal {
+        albedo: TextureRef,
+        #[serde(default)]
+        fuzz: f32,
+    },
+    // Either a metal preset or eta and k, optionally tinted by albedo
+    Conductor {
+        metal: Option<MetalDesc>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        albedo: Option<TextureRef>,
+        #[serde(default)]
+        roughness: f32,
+    },
+    // White light turns into attenuation_color after attenuation_distance inside, which
+    // defaults to one
+    Dielectric {
+        index_of_refraction: f32,
+        attenuation_color: Option<[f32; 3]>,
+        attenuation_distance: Option<f32>,
+    },
+    // Frosted glass, tint colors the transmitted light at the surface
+    RoughDielectric {
+        index_of_refraction: f32,
+        roughness: f32,
+        tint: Option<TextureRef>,
+        attenuation_color: Option<[f32; 3]>,
+        attenuation_distance: Option<f32>,
+    },
+    // A clear coat over the named base material, which can't be coated itself
+    Coated {
+        base: String,
+        #[serde(default = "default_coat_index_of_refraction")]
+        index_of_refraction: f32,
+        #[serde(default)]
+        roughness: f32,
+    },
+    // A Disney style uber material, missing parameters take the defaults of the model
+    Principled {
+        base_color: Option<TextureRef>,
+        metallic: Option<ScalarRef>,
+        roughness: Option<ScalarRef>,
+        specular: Option<ScalarRef>,
+        specular_tint: Option<ScalarRef>,
+        sheen: Option<ScalarRef>,
+        clearcoat: Option<ScalarRef>,
+        clearcoat_roughness: Option<ScalarRef>,
+        transmission: Option<ScalarRef>,
+        index_of_refraction: Option<f32>,
+    },
+    // The named base with its shading normal from a tangent space normal map
+    NormalMapped {
+        base: String,
+        map: TextureRef,
+        #[serde(default = "default_strength")]
+        strength: f32,
+    },
+    // The named base with its shading normal tilted by a height texture, scale is the height of a
+    // texture value of one
+    BumpMapped {
+        base: String,
+        height: TextureRef,
+        scale: f32,
+    },
+    // The named base with holes where opacity is below cutoff. Without a cutoff opacity is the
+    // probability of a hit.
+    Cutout {
+        base: String,
+        opacity: ScalarRef,
+        cutoff: Option<f32>,
+    },
+    // The named first material blended into the named second one by factor
+    Mix {
+        first: String,
+        second: String,
+        factor: ScalarRef,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum MetalDesc {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    sphere: Option<SphereDesc>,
+    #[serde(rename = "box")]
+    aa_box: Option<BoxDesc>,
+    rect: Option<RectDesc>,
+    mesh: Option<MeshDesc>,
+    node: Option<Spanned<String>>,
+    material: Option<Spanned<String>>,
+    // Applied in order
+    #[serde(default)]
+    transform: Vec<TransformDesc>,
+    velocity: Option<[f32; 3]>,
+    // Turns the geometry into a volume of constant density
+    density: Option<f32>,
+    #[serde(default)]
+    point_of_interest: bool,
+    #[serde(default)]
+    children: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    radius: f32,
+}
+
+// Spans from the origin to size
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxDesc {
+    size: [f32; 3],
+}
+
+// Centered at the origin
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RectDesc {
+    plane: RectPlaneDesc,
+    size: [f32; 2],
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RectPlaneDesc {
+    Xy,
+    Yz,
+    Xz,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate([f32; 3]),
+    Scale([f32; 3]),
+    ScaleUniform(f32),
+    // Counterclockwise in degrees
+    Rotate {
+        axis: [f32; 3],
+        angle: f32,
+    },
+    // Degrees around x, y and z
+    RotateEuler([f32; 3]),
+    LookAt {
+        target: [f32; 3],
+        #[serde(default = "default_up")]
+        up: [f32; 3],
+    },
+}
+
+#[derive(Debug)]
+pub struct SceneFileError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+pub fn load_scene_file<'a>(
+    wb: &'a WorldBuilder<'a>,
+    rng: &mut common::TRng,
+    path: &Path,
+) -> Result<World<'a>, SceneFileError> {
+    let source = std::fs::read_to_string(path).map_err(|e| SceneFileError {
+        path: path.to_path_buf(),
+        line: None,
+        message: e.to_string(),
+    })?;
+    let desc: SceneDesc = toml::from_str(&source).map_err(|e| SceneFileError {
+        path: path.to_path_buf(),
+        line: e.span().map(|span| line_of(&source, span.start)),
+        message: e.message().to_string(),
+    })?;
+
+    let mut loader = Loader {
+        wb,
+        rng,
+        path,
+        source: &source,
+        desc: &desc,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        nodes: HashMap::new(),
+        resolving: HashSet::new(),
+    };
+    let camera = desc.camera.build();
+    let background = match desc.background {
+        BackgroundDesc::Sky => BackgroundColor::Sky,
+        BackgroundDesc::Solid(color) => BackgroundColor::Solid {
+            color: Color::new_rgb_arr(color),
+        },
+    };
+    let mut scene = wb.new_group();
+    for object in &desc.objects {
+        scene = scene.add(loader.object(object)?);
+    }
+    Ok(scene.build().finish(wb, background, camera))
+}
+
+pub fn is_gltf(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("gltf") || e.eq_ignore_ascii_case("glb"))
+}
+
+// Renders the default scene of a glTF file with its first camera under the sky.
+pub fn load_gltf_file<'a>(
+    wb: &'a WorldBuilder<'a>,
+    path: &Path,
+) -> Result<World<'a>, SceneFileError> {
+    let error = |message: String| SceneFileError {
+        path: path.to_path_buf(),
+        line: None,
+        message,
+    };
+    let scene = wb
+        .new_scene_from_file_gltf(path)
+        .map_err(|e| error(e.to_string()))?;
+    let camera = scene
+        .camera
+        .ok_or_else(|| error("The scene has no perspective camera".to_string()))?;
+    Ok(scene.root.build().finish(wb, BackgroundColor::Sky, camera))
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn point(p: [f32; 3]) -> Point3 {
+    Point3::new(p[0], p[1], p[2])
+}
+
+fn dir(d: [f32; 3]) -> Dir3 {
+    Dir3::new(d[0], d[1], d[2])
+}
+
+impl CameraDesc {
+    fn build(&self) -> Camera {
+        let mut builder = Camera::build()
+            .vertical_fov(self.vertical_fov, self.aspect_ratio)
+            .position(point(self.position))
+            .look_at_focus(dir(self.up), point(self.look_at))
+            .aperture(self.aperture);
+        if let Some(distance) = self.focus_distance {
+            builder = builder.focus_distance(distance);
+        }
+        if let Some([start, end]) = self.motion_blur {
+            builder = builder.motion_blur(start, end);
+        }
+        builder.build()
+    }
+}
+
+struct Loader<'a, 'l> {
+    wb: &'a WorldBuilder<'a>,
+    rng: &'l mut common::TRng,
+    path: &'l Path,
+    source: &'l str,
+    desc: &'l SceneDesc,
+    textures: HashMap<String, &'a Texture<'a>>,
+    materials: HashMap<String, &'a Material<'a>>,
+    nodes: HashMap<String, NodeRef<'a>>,
+    // Named entries which are currently being built, used to detect cycles
+    resolving: HashSet<String>,
+}
+
+impl<'a, 'l> Loader<'a, 'l> {
+    fn error(&self, span: &Range<usize>, message: String) -> SceneFileError {
+        SceneFileError {
+            path: self.path.to_path_buf(),
+            line: Some(line_of(self.source, span.start)),
+            message,
+        }
+    }
+
+    // White light turns into the attenuation color after the distance, which defaults to one.
+    fn absorption(
+        &self,
+        attenuation_color: Option<[f32; 3]>,
+        attenuation_distance: Option<f32>,
+        span: &Range<usize>,
+    ) -> Result<Color, SceneFileError> {
+        let distance = attenuation_distance.unwrap_or(1.0);
+        match attenuation_color {
+            Some(color) if color.iter().any(|&c| c.is_nan() || c <= 0.0 || c > 1.0) => Err(self
+                .error(
+                    span,
+                    format!("attenuation_color must be in (0, 1], got {color:?}"),
+                )),
+            Some(_) if distance.is_nan() || distance <= 0.0 => Err(self.error(
+                span,
+                format!("attenuation_distance must be positive, got {distance}"),
+            )),
+            Some(color) => Ok(absorption_from_color_at_distance(
+                Color::new_rgb_arr(color),
+                distance,
+            )),
+            None if attenuation_distance.is_some() => Err(self.error(
+                span,
+                "attenuation_distance requires an attenuation_color".to_string(),
+            )),
+            None => Ok(Color::BLACK),
+        }
+    }
+
+    fn file(&self, path: &Path, span: &Range<usize>) -> Result<PathBuf, SceneFileError> {
+        let full_path = self.path.parent().unwrap_or(Path::new("")).join(path);
+        if full_path.is_file() {
+            Ok(full_path)
+        } else {
+            Err(self.error(span, format!("File `{}` not found", full_path.display())))
+        }
+    }
+
+    fn begin_resolving(
+        &mut self,
+        kind: &str,
+        name: &str,
+        span: &Range<usize>,
+    ) -> Result<(), SceneFileError> {
+        if self.resolving.insert(format!("{kind}.{name}")) {
+            Ok(())
+        } else {
+            Err(self.error(span, format!("{kind}.{name} references itself")))
+        }
+    }
+
+    fn end_resolving(&mut self, kind: &str, name: &str) {
+        self.resolving.remove(&format!("{kind}.{name}"));
+    }
+
+    fn texture(
+        &mut self,
+        texture: &TextureRef,
+        span: &Range<usize>,
+    ) -> Result<&'a Texture<'a>, SceneFileError> {
+        match texture {
+            TextureRef::Color(color) => Ok(self.wb.texture_solid(Color::new_rgb_arr(*color))),
+            TextureRef::Name(name) => self.named_texture(name, span),
+        }
+    }
+
+    fn scalar(
+        &mut self,
+        scalar: &ScalarRef,
+        span: &Range<usize>,
+    ) -> Result<&'a Texture<'a>, SceneFileError> {
+        match scalar {
+            ScalarRef::Value(value) => Ok(self.wb.texture_scalar(*value)),
+            ScalarRef::Name(name) => self.named_texture(name, span),
+        }
+    }
+
+    // The default texture if the scalar is missing
+    fn scalar_or(
+        &mut self,
+        scalar: Option<&ScalarRef>,
+        default: &'a Texture<'a>,
+        span: &Range<usize>,
+    ) -> Result<&'a Texture<'a>, SceneFileError> {
+        match scalar {
+            Some(scalar) => self.scalar(scalar, span),
+            None => Ok(default),
+        }
+    }
+
+    fn named_texture(
+        &mut self,
+        name: &str,
+        span: &Range<usize>,
+    ) -> Result<&'a Texture<'a>, SceneFileError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture);
+        }
+        let desc = self.desc;
+        let Some(texture_desc) = desc.textures.get(name) else {
+            return Err(self.error(span, format!("Unknown texture `{name}`")));
+        };
+        let span = texture_desc.span();
+        self.begin_resolving("textures", name, &span)?;
+        let wb = self.wb;
+        let texture = match texture_desc.get_ref() {
+            TextureDesc::Solid { color } => wb.texture_solid(Color::new_rgb_arr(*color)),
+            TextureDesc::Image { path, linear } => {
+                let path = self.file(path, &span)?;
+                wb.texture_image_from_path(&path, !linear)
+                    .map_err(|e| self.error(&span, format!("textures.{name}.path: {e}")))?
+            }
+            TextureDesc::Marble { scale } => wb.texture_marble(*scale, self.rng),
+            TextureDesc::Checker {
+                inv_frequency,
+                even,
+                odd,
+            } => {
+                let even = self.texture(even, &span)?;
+                let odd = self.texture(odd, &span)?;
+                wb.texture_checker(*inv_frequency, even, odd)
+            }
+            TextureDesc::Channel { texture, channel } => {
+                if *channel > 3 {
+                    let message = format!("textures.{name}.channel must be 0, 1, 2 or 3");
+                    return Err(self.error(&span, message));
+                }
+                let texture = self.texture(texture, &span)?;
+                wb.texture_channel(texture, *channel)
+            }
+        };
+        self.end_resolving("textures", name);
+        self.textures.insert(name.to_string(), texture);
+        Ok(texture)
+    }
+
+    fn material(&mut self, name: &Spanned<String>) -> Result<&'a Material<'a>, SceneFileError> {
+        if let Some(material) = self.materials.get(name.get_ref()) {
+            return Ok(material);
+        }
+        let desc = self.desc;
+        let Some(material_desc) = desc.materials.get(name.get_ref()) else {
+            return Err(self.error(
+                &name.span(),
+                format!("Unknown material `{}`", name.get_ref()),
+            ));
+        };
+        let span = material_desc.span();
+        let wb = self.wb;
+        self.begin_resolving("materials", name.get_ref(), &span)?;
+        let material = match material_desc.get_ref() {
+            MaterialDesc::Lambert { albedo } => wb.material_lambert(self.texture(albedo, &span)?),
+            MaterialDesc::OrenNayar { albedo, sigma } => {
+                let albedo = self.texture(albedo, &span)?;
+                let sigma = self.scalar(sigma, &span)?;
+                wb.material_oren_nayar(albedo, sigma)
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                wb.material_metal(self.texture(albedo, &span)?, *fuzz)
+            }
+            MaterialDesc::Conductor {
+                metal,
+                eta,
+                k,
+                albedo,
+                roughness,
+            } => {
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(metal), None, None) => {
+                        let preset = match metal {
+                            MetalDesc::Gold => ConductorPreset::Gold,
+                            MetalDesc::Copper => ConductorPreset::Copper,
+                            MetalDesc::Aluminium => ConductorPreset::Aluminium,
+                            MetalDesc::Silver => ConductorPreset::Silver,
+                        };
+                        preset.complex_ior()
+                    }
+                    (None, Some(eta), Some(k)) => {
+                        (Color::new_rgb_arr(*eta), Color::new_rgb_arr(*k))
+                    }
+                    _ => {
+                        return Err(self.error(
+                            &span,
+                            "conductor needs either `metal` or both `eta` and `k`".to_string(),
+                        ))
+                    }
+                };
+                let albedo = match albedo {
+                    Some(albedo) => self.texture(albedo, &span)?,
+                    None => wb.texture_solid(Color::WHITE),
+                };
+                wb.material_conductor(albedo, eta, k, *roughness)
+            }
+            MaterialDesc::Dielectric {
+                index_of_refraction,
+                attenuation_color,
+                attenuation_distance,
+            } => wb.material_dielectric_absorbing(
+                *index_of_refraction,
+                self.absorption(*attenuation_color, *attenuation_distance, &span)?,
+            ),
+            MaterialDesc::RoughDielectric {
+                index_of_refraction,
+                roughness,
+                tint,
+                attenuation_color,
+                attenuation_distance,
+            } => {
+                let tint = match tint {
+                    Some(tint) => self.texture(tint, &span)?,
+                    None => wb.texture_solid(Color::WHITE),
+                };
+                wb.material_rough_dielectric(
+                    *index_of_refraction,
+                    tint,
+                    *roughness,
+                    self.absorption(*attenuation_color, *attenuation_distance, &span)?,
+                )
+            }
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                index_of_refraction,
+            } => {
+                let base_color = match base_color {
+                    Some(base_color) => self.texture(base_color, &span)?,
+                    None => wb.texture_solid(Color::new_rgb(0.8, 0.8, 0.8)),
+                };
+                let defaults = wb.principled(base_color);
+                let principled = Principled {
+                    metallic: self.scalar_or(metallic.as_ref(), defaults.metallic, &span)?,
+                    roughness: self.scalar_or(roughness.as_ref(), defaults.roughness, &span)?,
+                    specular: self.scalar_or(specular.as_ref(), defaults.specular, &span)?,
+                    specular_tint: self.scalar_or(
+                        specular_tint.as_ref(),
+                        defaults.specular_tint,
+                        &span,
+                    )?,
+                    sheen: self.scalar_or(sheen.as_ref(), defaults.sheen, &span)?,
+                    clearcoat: self.scalar_or(clearcoat.as_ref(), defaults.clearcoat, &span)?,
+                    clearcoat_roughness: self.scalar_or(
+                        clearcoat_roughness.as_ref(),
+                        defaults.clearcoat_roughness,
+                        &span,
+                    )?,
+                    transmission: self.scalar_or(
+                        transmission.as_ref(),
+                        defaults.transmission,
+                        &span,
+                    )?,
+                    index_of_refraction: index_of_refraction
+                        .unwrap_or(defaults.index_of_refraction),
+                    ..defaults
+                };
+                wb.material_principled(principled)
+            }
+            MaterialDesc::NormalMapped {
+                base,
+                map,
+                strength,
+            } => {
+                let base = self.material(&Spanned::new(span.clone(), base.clone()))?;
+                wb.material_normal_mapped(base, self.texture(map, &span)?, *strength)
+            }
+            MaterialDesc::BumpMapped {
+                base,
+                height,
+                scale,
+            } => {
+                let base = self.material(&Spanned::new(span.clone(), base.clone()))?;
+                wb.material_bump_mapped(base, self.texture(height, &span)?, *scale)
+            }
+            MaterialDesc::Cutout {
+                base,
+                opacity,
+                cutoff,
+            } => {
+                let base = self.material(&Spanned::new(span.clone(), base.clone()))?;
+                let opacity = self.scalar(opacity, &span)?;
+                wb.material_cutout(base, opacity, *cutoff)
+            }
+            MaterialDesc::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let first = self.material(&Spanned::new(span.clone(), first.clone()))?;
+                let second = self.material(&Spanned::new(span.clone(), second.clone()))?;
+                let factor = self.scalar(factor, &span)?;
+                wb.material_mix(first, second, factor)
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                wb.material_diffuse_light(self.texture(emit, &span)?)
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                wb.material_isotropic(self.texture(albedo, &span)?)
+            }
+            MaterialDesc::Coated {
+                base,
+                index_of_refraction,
+                roughness,
+            } => {
+                // Spans are lost inside tagged enums, errors point at the coated material
+                let base_material = self.material(&Spanned::new(span.clone(), base.clone()))?;
+                if base_material.is_coated() {
+                    return Err(self.error(&span, format!("the base `{base}` is coated already")));
+                }
+                wb.material_coated(base_material, *index_of_refraction, *roughness)
+            }
+        };
+        self.end_resolving("materials", name.get_ref());
+        self.materials.insert(name.get_ref().clone(), material);
+        Ok(material)
+    }
+
+    fn named_node(&mut self, name: &Spanned<String>) -> Result<NodeRef<'a>, SceneFileError> {
+        if let Some(node) = self.nodes.get(name.get_ref()) {
+            return Ok(node.clone());
+        }
+        let desc = self.desc;
+        let Some(node_desc) = desc.nodes.get(name.get_ref()) else {
+            return Err(self.error(&name.span(), format!("Unknown node `{}`", name.get_ref())));
+        };
+        self.begin_resolving("nodes", name.get_ref(), &name.span())?;
+        let node = self.object(node_desc)?;
+        self.end_resolving("nodes", name.get_ref());
+        self.nodes.insert(name.get_ref().clone(), node.clone());
+        Ok(node)
+    }
+
+    fn object(&mut self, object: &Spanned<ObjectDesc>) -> Result<NodeRef<'a>, SceneFileError> {
+        let span = object.span();
+        let desc = object.get_ref();
+        let wb = self.wb;
+        let shape_count = [
+            desc.sphere.is_some(),
+            desc.aa_box.is_some(),
+            desc.rect.is_some(),
+            desc.mesh.is_some(),
+            desc.node.is_some(),
+        ]
+        .into_iter()
+        .filter(|s| *s)
+        .count();
+        if shape_count > 1 {
+            return Err(self.error(
+                &span,
+                "An object holds at most one of sphere, box, rect, mesh and node".to_string(),
+            ));
+        }
+        let has_geometry = shape_count == 1 && desc.node.is_none();
+        // Checked before loading any mesh, as the builder panics on these
+        if let Some(density) = desc.density {
+            if desc.mesh.is_some() {
+                return Err(self.error(&span, "meshes can't have a density".to_string()));
+            }
+            if !has_geometry || !(0.0..1.0).contains(&density) {
+                return Err(self.error(
+                    &span,
+                    format!("density must be in [0, 1) and requires a geometry, got {density}"),
+                ));
+            }
+        }
+        // Follows the transforms, as look_at depends on where the origin has been moved to
+        let mut transformation = Transformation::ZERO;
+        for transform in &desc.transform {
+            let invalid = match *transform {
+                TransformDesc::Scale(factors) if factors.contains(&0.0) => {
+                    Some(format!("scale factors must not be zero, got {factors:?}"))
+                }
+                TransformDesc::ScaleUniform(0.0) => Some("scale must not be zero".to_string()),
+                TransformDesc::Rotate { axis, .. } if dir(axis).length_squared() == 0.0 => {
+                    Some("rotation axis must not be zero".to_string())
+                }
+                TransformDesc::LookAt { target, up } => {
+                    let forward = point(target) - transformation.apply_point(Point3::ORIGIN);
+                    let up = dir(up);
+                    if forward.length_squared() == 0.0 {
+                        Some(format!(
+                            "look_at target {target:?} is at the object's origin"
+                        ))
+                    } else if Dir3::cross(forward, up).length_squared()
+                        <= 1e-12 * forward.length_squared() * up.length_squared()
+                    {
+                        Some("look_at up must not be zero or parallel to the target".to_string())
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            if let Some(message) = invalid {
+                return Err(self.error(&span, message));
+            }
+            transformation = match *transform {
+                TransformDesc::Translate(offset) => transformation.translate(dir(offset)),
+                TransformDesc::Scale(factors) => transformation.scale(dir(factors)),
+                TransformDesc::ScaleUniform(factor) => transformation.scale_uniform(factor),
+                TransformDesc::Rotate { axis, angle } => {
+                    transformation.rotate_axis(dir(axis), angle)
+                }
+                TransformDesc::RotateEuler([x, y, z]) => transformation.rotate_euler(x, y, z),
+                TransformDesc::LookAt { target, up } => {
+                    transformation.look_at(point(target), dir(up))
+                }
+            };
+        }
+        let material = match &desc.material {
+            Some(name) if has_geometry => Some(self.material(name)?),
+            Some(name) => {
+                return Err(self.error(
+                    &name.span(),
+                    "material is only used by sphere, box, rect and mesh".to_string(),
+                ))
+            }
+            // Meshes fall back to the materials of their files
+            None if has_geometry && desc.mesh.is_none() => {
+                return Err(self.error(&span, "missing field `material`".to_string()))
+            }
+            None => None,
+        };
+
+        let mut node: NodeBuilder<'a> = if let Some(sphere) = &desc.sphere {
+            wb.new_obj_sphere(sphere.radius, material.unwrap())
+        } else if let Some(aa_box) = &desc.aa_box {
+            let [width, height, depth] = aa_box.size;
+            wb.new_obj_box(width, height, depth, material.unwrap())
+        } else if let Some(rect) = &desc.rect {
+            let [s0, s1] = rect.size;
+            match rect.plane {
+                RectPlaneDesc::Xy => wb.new_obj_rect_xy(Point3::ORIGIN, s0, s1, material.unwrap()),
+                RectPlaneDesc::Yz => wb.new_obj_rect_yz(Point3::ORIGIN, s0, s1, material.unwrap()),
+                RectPlaneDesc::Xz => wb.new_obj_rect_xz(Point3::ORIGIN, s0, s1, material.unwrap()),
+            }
+        } else if let Some(mesh) = &desc.mesh {
+            let path = self.file(&mesh.path, &span)?;
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase());
+            let default_material = || wb.material_lambert_solid(Color::new_rgb(0.8, 0.8, 0.8));
+            let loaded = match (extension.as_deref(), material) {
+                (Some("gltf" | "glb"), Some(_)) => {
+                    return Err(self.error(
+                        &span,
+                        "glTF meshes use their own materials, remove `material`".to_string(),
+                    ))
+                }
+                // Cameras of the file are ignored
+                (Some("gltf" | "glb"), None) => wb
+                    .new_scene_from_file_gltf(&path)
+                    .map(|scene| scene.root)
+                    .map_err(|e| e.to_string()),
+                (Some("ply"), Some(material)) => wb
+                    .new_mesh_from_file_ply_uniform_material(&path, material)
+                    .map_err(|e| e.to_string()),
+                (Some("ply"), None) => wb
+                    .new_mesh_from_file_ply_vertex_colors(&path, default_material())
+                    .map_err(|e| e.to_string()),
+                (Some("stl"), material) => wb
+                    .new_mesh_from_file_stl_uniform_material(
+                        &path,
+                        material.unwrap_or_else(default_material),
+                    )
+                    .map_err(|e| e.to_string()),
+                (_, Some(material)) => wb
+                    .new_mesh_from_file_obj_uniform_material(&path, material)
+                    .map_err(|e| e.to_string()),
+                (_, None) => wb
+                    .new_mesh_from_file_obj(&path, default_material())
+                    .map_err(|e| e.to_string()),
+            };
+            loaded.map_err(|e| self.error(&span, format!("{}: {e}", path.display())))?
+        } else if let Some(name) = &desc.node {
+            wb.new_group().add(self.named_node(name)?)
+        } else {
+            wb.new_group()
+        };
+
+        if let Some(density) = desc.density {
+            node = node.set_all_geo_densitity(density);
+        }
+        if desc.point_of_interest {
+            node = node.set_all_geo_as_poi();
+        }
+        for child in &desc.children {
+            node = node.add(self.object(child)?);
+        }
+        for transform in &desc.transform {
+            node = match *transform {
+                TransformDesc::Translate(offset) => node.translate(dir(offset)),
+                TransformDesc::Scale(factors) => node.scale(dir(factors)),
+                TransformDesc::ScaleUniform(factor) => node.scale_uniform(factor),
+                TransformDesc::Rotate { axis, angle } => node.rotate_axis(dir(axis), angle),
+                TransformDesc::RotateEuler([x, y, z]) => node
+                    .rotate_axis(Dir3::RIGHT, x)
+                    .rotate_axis(Dir3::UP, y)
+                    .rotate_axis(Dir3::BACKWARD, z),
+                TransformDesc::LookAt { target, up } => node.look_at(point(target), dir(up)),
+            };
+        }
+        if let Some(velocity) = desc.velocity {
+            node = node.animate_moving(dir(velocity));
+        }
+        Ok(node.build())
+    }
+}
@@ -1,69 +1,275 @@
 use ray_tracing_in_a_weekend::{
     rect_geometry::{RectGeometry, RectPlane},
+    mesh_geometry::{Mesh, MeshGeometry, MeshSamplingGeometry},
+    triangle_geometry::TriangleGeometry,
     *,
 };
-use std::{ops::Range, rc::Rc};
+use std::{collections::HashMap, ops::Range, rc::Rc};
 
 pub struct WorldBuilder<'a> {
     pub arena: &'a mut bumpalo::Bump,
+    pub bvh_strategy: BvhStrategy,
 }
 
 impl<'a> WorldBuilder<'a> {
     pub fn new(arena: &'a mut bumpalo::Bump) -> Self {
-        Self { arena }
+        Self {
+            arena,
+            bvh_strategy: BvhStrategy::default(),
+        }
     }
 
-    pub fn texture_solid(&self, color: Color) -> &Texture {
+    pub fn texture_solid(&self, color: Color) -> &Texture<'_> {
         self.alloc(Texture::Solid { color })
     }
     pub fn texture_image_from_file(
         &self,
         path: &std::path::Path,
         fmt: image::ImageFormat,
-    ) -> &Texture {
+    ) -> &Texture<'_> {
         let file = std::fs::OpenOptions::new().read(true).open(path).unwrap();
         let reader = std::io::BufReader::new(file);
-        let image = self.alloc(image::load(reader, fmt).unwrap());
-
-        self.alloc(Texture::Image {
-            image: image.as_rgb8().unwrap(),
-        })
+        let image = image::load(reader, fmt).unwrap();
+        self.texture_image_srgb(image.into_rgba8())
     }
-    pub fn texture_marble(&self, scale : f32, rng : &mut common::TRng) -> &Texture {
+    // Guesses the format from the file name. Images without an alpha channel are opaque. srgb is
+    // false for data like normal or height maps.
+    pub fn texture_image_from_path(
+        &self,
+        path: &std::path::Path,
+        srgb: bool,
+    ) -> image::ImageResult<&Texture<'_>> {
+        let image = self.alloc(image::open(path)?.into_rgba8());
+        Ok(self.alloc(Texture::Image { image, srgb }))
+    }
+    // An image with linear colors, like a generated normal map
+    pub fn texture_image(&self, image: image::RgbaImage) -> &Texture<'_> {
+        let image = self.alloc(image);
+        self.alloc(Texture::Image { image, srgb: false })
+    }
+    // An image with sRGB encoded colors, like a photo
+    pub fn texture_image_srgb(&self, image: image::RgbaImage) -> &Texture<'_> {
+        let image = self.alloc(image);
+        self.alloc(Texture::Image { image, srgb: true })
+    }
+    pub fn texture_marble(&self, scale : f32, rng : &mut common::TRng) -> &Texture<'_> {
         self.alloc(Texture::Marble {
             scale,
             noise: Perlin::new(8, rng),
         })     
     }
-    pub fn texture_checker(&'a self, inv_frequency : f32, tex_even : &'a Texture, tex_odd : &'a Texture) -> &Texture {
+    pub fn texture_checker(&'a self, inv_frequency : f32, tex_even : &'a Texture, tex_odd : &'a Texture) -> &'a Texture<'a> {
         self.alloc(Texture::Checker { inv_frequency, even: tex_even, odd: tex_odd })
     }
-    pub fn material_diffuse_light_solid(&self, color: Color) -> &Material {
+    // Multiplies the color of texture by factor and its opacity by alpha
+    pub fn texture_scaled(
+        &'a self,
+        texture: &'a Texture,
+        factor: Color,
+        alpha: f32,
+    ) -> &'a Texture<'a> {
+        if factor == Color::WHITE && alpha == 1.0 {
+            return texture;
+        }
+        self.alloc(Texture::Scaled {
+            texture,
+            factor,
+            alpha,
+        })
+    }
+    // A gray texture for the scalar parameters of a principled material
+    pub fn texture_scalar(&self, value: f32) -> &Texture<'_> {
+        self.texture_solid(Color::WHITE * value)
+    }
+    // channel 0, 1, 2 or 3 for red, green, blue or alpha
+    pub fn texture_channel(&'a self, texture: &'a Texture, channel: usize) -> &'a Texture<'a> {
+        assert!(channel <= 3, "Channel must be 0, 1, 2 or 3");
+        self.alloc(Texture::Channel { texture, channel })
+    }
+    // The interpolated vertex colors of meshes, average is their mean
+    pub fn texture_vertex_color(&self, average: Color) -> &Texture<'_> {
+        self.alloc(Texture::VertexColor { average })
+    }
+    pub fn material_diffuse_light_solid(&self, color: Color) -> &Material<'_> {
         let emit = self.texture_solid(color);
         self.alloc(Material::DiffuseLight { emit })
     }
-    pub fn material_lambert_solid(&self, color: Color) -> &Material {
+    pub fn material_diffuse_light(&'a self, emit: &'a Texture) -> &'a Material<'a> {
+        self.alloc(Material::DiffuseLight { emit })
+    }
+    pub fn material_lambert_solid(&self, color: Color) -> &Material<'_> {
         let albedo = self.texture_solid(color);
         self.material_lambert(albedo)
     }
-    pub fn material_lambert(&'a self, albedo: &'a Texture) -> &'a Material {
+    pub fn material_lambert(&'a self, albedo: &'a Texture) -> &'a Material<'a> {
         self.alloc(Material::Lambert { albedo })
     }
 
-    pub fn material_metal_solid(&self, color: Color, fuzz: f32) -> &Material {
+    // sigma in radians, for example 0.35 for plaster or 0.5 for the moon
+    pub fn material_oren_nayar_solid(&self, color: Color, sigma: f32) -> &Material<'_> {
+        let albedo = self.texture_solid(color);
+        self.material_oren_nayar(albedo, self.texture_scalar(sigma))
+    }
+    pub fn material_oren_nayar(
+        &'a self,
+        albedo: &'a Texture,
+        sigma: &'a Texture,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::OrenNayar { albedo, sigma })
+    }
+    pub fn material_metal_solid(&self, color: Color, fuzz: f32) -> &Material<'_> {
         let albedo = self.texture_solid(color);
         self.alloc(Material::Metal { albedo, fuzz })
     }
-    pub fn material_dielectric(&self, index_of_refraction: f32) -> &Material {
+    pub fn material_metal(&'a self, albedo: &'a Texture, fuzz: f32) -> &'a Material<'a> {
+        self.alloc(Material::Metal { albedo, fuzz })
+    }
+    pub fn material_conductor(
+        &'a self,
+        albedo: &'a Texture,
+        eta: Color,
+        k: Color,
+        roughness: f32,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::Conductor {
+            albedo,
+            eta,
+            k,
+            ggx: Ggx::new_roughness(roughness),
+        })
+    }
+    pub fn material_conductor_preset(&self, preset: ConductorPreset, roughness: f32) -> &Material<'_> {
+        let (eta, k) = preset.complex_ior();
+        self.material_conductor(self.texture_solid(Color::WHITE), eta, k, roughness)
+    }
+    pub fn material_dielectric(&self, index_of_refraction: f32) -> &Material<'_> {
+        self.material_dielectric_absorbing(index_of_refraction, Color::BLACK)
+    }
+    // Colored glass or liquids, see absorption_from_color_at_distance
+    pub fn material_dielectric_absorbing(
+        &self,
+        index_of_refraction: f32,
+        absorption: Color,
+    ) -> &Material<'_> {
         self.alloc(Material::Dielectric {
             index_of_refraction,
+            absorption,
+        })
+    }
+
+    // Frosted glass, tint colors the transmitted light at the surface and absorption inside
+    pub fn material_rough_dielectric(
+        &'a self,
+        index_of_refraction: f32,
+        tint: &'a Texture,
+        roughness: f32,
+        absorption: Color,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::RoughDielectric {
+            index_of_refraction,
+            tint,
+            ggx: Ggx::new_roughness(roughness),
+            absorption,
+        })
+    }
+
+    // Car paint, varnished wood or plastic, base must not be coated itself
+    pub fn material_coated(
+        &'a self,
+        base: &'a Material<'a>,
+        index_of_refraction: f32,
+        roughness: f32,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::Coated {
+            base,
+            index_of_refraction,
+            ggx: Ggx::new_roughness(roughness),
+        })
+    }
+
+    // The defaults of the Disney model for a dielectric of the given color, to be changed with
+    // struct update syntax before material_principled
+    pub fn principled(&'a self, base_color: &'a Texture) -> Principled<'a> {
+        Principled {
+            base_color,
+            metallic: self.texture_scalar(0.0),
+            roughness: self.texture_scalar(0.5),
+            specular: self.texture_scalar(0.5),
+            specular_tint: self.texture_scalar(0.0),
+            sheen: self.texture_scalar(0.0),
+            clearcoat: self.texture_scalar(0.0),
+            clearcoat_roughness: self.texture_scalar(0.1),
+            transmission: self.texture_scalar(0.0),
+            index_of_refraction: 1.5,
+        }
+    }
+    pub fn material_principled(&'a self, principled: Principled<'a>) -> &'a Material<'a> {
+        self.alloc(Material::Principled(principled))
+    }
+
+    // See Material::NormalMapped, a strength of one keeps the map as it is
+    pub fn material_normal_mapped(
+        &'a self,
+        base: &'a Material<'a>,
+        map: &'a Texture,
+        strength: f32,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::NormalMapped {
+            base,
+            map,
+            strength,
+        })
+    }
+    // scale is the height of a texture value of one in world units
+    pub fn material_bump_mapped(
+        &'a self,
+        base: &'a Material<'a>,
+        height: &'a Texture,
+        scale: f32,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::BumpMapped {
+            base,
+            height,
+            scale,
+        })
+    }
+
+    // Leaves or fences. Without a cutoff the opacity is the probability of a hit.
+    pub fn material_cutout(
+        &'a self,
+        base: &'a Material<'a>,
+        opacity: &'a Texture,
+        cutoff: Option<f32>,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::Cutout {
+            base,
+            opacity,
+            cutoff,
         })
     }
 
-    pub fn material_isotropic_solid(&self, color: Color) -> &Material {
+    // Blends first into second by the red channel of factor, a constant factor comes from
+    // texture_scalar
+    pub fn material_mix(
+        &'a self,
+        first: &'a Material<'a>,
+        second: &'a Material<'a>,
+        factor: &'a Texture,
+    ) -> &'a Material<'a> {
+        self.alloc(Material::Mix {
+            first,
+            second,
+            factor,
+        })
+    }
+
+    pub fn material_isotropic_solid(&self, color: Color) -> &Material<'_> {
         let albedo = self.texture_solid(color);
         self.alloc(Material::Isotropic { albedo })
     }
+    pub fn material_isotropic(&'a self, albedo: &'a Texture) -> &'a Material<'a> {
+        self.alloc(Material::Isotropic { albedo })
+    }
 
     fn geo_rect_xy(&self, center: Point3, s0: f32, s1: f32) -> Geometry {
         self.geo_rect(RectPlane::Xy, center, s0, s1)
@@ -102,17 +308,65 @@ impl<'a> WorldBuilder<'a> {
         self.arena.alloc(v)
     }
 
-    pub fn new_group(&self) -> NodeBuilder {
+    pub fn new_group(&self) -> NodeBuilder<'_> {
         NodeBuilder(Box::new(Node {
             geo: Vec::new(),
+            meshes: Vec::new(),
             transformation: Transformation::ZERO,
             moving_animation: Dir3::ZERO,
             children: Vec::new(),
         }))
     }
-    pub fn new_obj(&self, geometry: Geometry, material: &'a Material<'a>) -> NodeBuilder {
+    pub fn new_obj(&self, geometry: Geometry, material: &'a Material<'a>) -> NodeBuilder<'_> {
         NodeBuilder(Box::new(Node {
             geo: vec![(geometry, material, false, 1.0)],
+            meshes: Vec::new(),
+            transformation: Transformation::ZERO,
+            moving_animation: Dir3::ZERO,
+            children: Vec::new(),
+        }))
+    }
+    // Stores the triangles as indexed mesh, triangles of the same material may come in any order.
+    pub fn new_mesh(
+        &'a self,
+        triangles: impl IntoIterator<Item = (TriangleGeometry, &'a Material<'a>)>,
+    ) -> NodeBuilder<'a> {
+        let mut materials = Vec::<&'a Material<'a>>::new();
+        let mut ids_by_material = HashMap::<*const Material, u32>::new();
+        let mut material_ids = Vec::new();
+        let triangles = triangles
+            .into_iter()
+            .map(|(t, material)| {
+                let id = *ids_by_material.entry(material).or_insert_with(|| {
+                    materials.push(material);
+                    materials.len() as u32 - 1
+                });
+                material_ids.push(id);
+                t
+            })
+            .collect::<Vec<_>>();
+        if materials.len() <= 1 {
+            material_ids.clear();
+        }
+        self.new_mesh_indexed(MeshGeometry::from_triangles(&triangles), materials, material_ids)
+    }
+    // material_ids holds an index into materials per triangle, it may be empty for a single
+    // material.
+    pub fn new_mesh_indexed(
+        &'a self,
+        geometry: MeshGeometry,
+        materials: Vec<&'a Material<'a>>,
+        material_ids: Vec<u32>,
+    ) -> NodeBuilder<'a> {
+        let mesh = (
+            &*self.alloc(geometry),
+            &*self.arena.alloc_slice_copy(&materials),
+            &*self.arena.alloc_slice_copy(&material_ids),
+            false,
+        );
+        NodeBuilder(Box::new(Node {
+            geo: Vec::new(),
+            meshes: vec![mesh],
             transformation: Transformation::ZERO,
             moving_animation: Dir3::ZERO,
             children: Vec::new(),
@@ -125,7 +379,7 @@ impl<'a> WorldBuilder<'a> {
         size0: f32,
         size1: f32,
         material: &'a Material,
-    ) -> NodeBuilder {
+    ) -> NodeBuilder<'_> {
         self.new_obj(self.geo_rect_yz(position, size0, size1), material)
     }
 
@@ -135,7 +389,7 @@ impl<'a> WorldBuilder<'a> {
         size0: f32,
         size1: f32,
         material: &'a Material,
-    ) -> NodeBuilder {
+    ) -> NodeBuilder<'_> {
         self.new_obj(self.geo_rect_xz(position, size0, size1), material)
     }
 
@@ -145,7 +399,7 @@ impl<'a> WorldBuilder<'a> {
         size0: f32,
         size1: f32,
         material: &'a Material,
-    ) -> NodeBuilder {
+    ) -> NodeBuilder<'_> {
         self.new_obj(self.geo_rect_xy(position, size0, size1), material)
     }
 
@@ -155,10 +409,10 @@ impl<'a> WorldBuilder<'a> {
         height: f32,
         depth: f32,
         material: &'a Material,
-    ) -> NodeBuilder {
+    ) -> NodeBuilder<'_> {
         self.new_obj(self.geo_box(width, height, depth), material)
     }
-    pub fn new_obj_sphere(&self, radius: f32, material: &'a Material) -> NodeBuilder {
+    pub fn new_obj_sphere(&self, radius: f32, material: &'a Material) -> NodeBuilder<'_> {
         self.new_obj(self.geo_sphere(radius), material)
     }
     pub fn new_obj_sphere_ground(
@@ -166,33 +420,171 @@ impl<'a> WorldBuilder<'a> {
         radius: f32,
         height: f32,
         material: &'a Material,
-    ) -> NodeBuilder {
+    ) -> NodeBuilder<'_> {
         self.new_obj_sphere(radius, material)
             .translate(Dir3::new(0.0, height - radius, 0.0))
     }
     pub fn new_mesh_from_file_obj_uniform_material(
-        &self,
+        &'a self,
         path: &std::path::Path,
         material: &'a Material<'a>,
-    ) -> NodeBuilder {
-        let file = std::fs::OpenOptions::new().read(true).open(path).unwrap();
+    ) -> Result<NodeBuilder<'a>, crate::obj_loader::ObjError> {
+        let file = std::fs::File::open(path).map_err(crate::obj_loader::ObjError::IoError)?;
         let reader = std::io::BufReader::new(file);
-        let tris = crate::obj_loader::load_obj_mesh(reader).unwrap();
-        let geo = tris
-            .into_iter()
-            .map(|t| (Geometry::Triangle(t), material, false, 1.0))
-            .collect::<Vec<_>>();
-        NodeBuilder(Box::new(Node {
-            geo,
-            transformation: Transformation::ZERO,
-            moving_animation: Dir3::ZERO,
-            children: Vec::new(),
-        }))
+        let tris = crate::obj_loader::load_obj_mesh(reader)?.triangles;
+        Ok(self.new_mesh(tris.into_iter().map(|t| (t, material))))
+    }
+    pub fn new_mesh_from_file_ply_uniform_material(
+        &'a self,
+        path: &std::path::Path,
+        material: &'a Material<'a>,
+    ) -> Result<NodeBuilder<'a>, crate::ply_loader::PlyError> {
+        let file = std::fs::File::open(path).map_err(crate::ply_loader::PlyError::IoError)?;
+        let reader = std::io::BufReader::new(file);
+        let mesh = crate::ply_loader::load_ply_mesh(reader)?;
+        Ok(self.new_mesh_indexed(mesh.geometry, vec![material], Vec::new()))
+    }
+    // Diffuse with the vertex colors interpolated over the triangles, default_material is used if
+    // the vertices have no colors.
+    pub fn new_mesh_from_file_ply_vertex_colors(
+        &'a self,
+        path: &std::path::Path,
+        default_material: &'a Material<'a>,
+    ) -> Result<NodeBuilder<'a>, crate::ply_loader::PlyError> {
+        let file = std::fs::File::open(path).map_err(crate::ply_loader::PlyError::IoError)?;
+        let reader = std::io::BufReader::new(file);
+        let mut mesh = crate::ply_loader::load_ply_mesh(reader)?;
+        let Some(vertex_colors) = mesh.vertex_colors else {
+            return Ok(self.new_mesh_indexed(mesh.geometry, vec![default_material], Vec::new()));
+        };
+        let average = vertex_colors.iter().copied().sum::<Color>() / vertex_colors.len() as f32;
+        let material = self.material_lambert(self.texture_vertex_color(average));
+        mesh.geometry.colors = vertex_colors;
+        Ok(self.new_mesh_indexed(mesh.geometry, vec![material], Vec::new()))
+    }
+    pub fn new_mesh_from_file_stl_uniform_material(
+        &'a self,
+        path: &std::path::Path,
+        material: &'a Material<'a>,
+    ) -> Result<NodeBuilder<'a>, crate::stl_loader::StlError> {
+        let file = std::fs::File::open(path).map_err(crate::stl_loader::StlError::IoError)?;
+        let reader = std::io::BufReader::new(file);
+        let tris = crate::stl_loader::load_stl_mesh(reader)?;
+        Ok(self.new_mesh(tris.into_iter().map(|t| (t, material))))
+    }
+    // Uses the materials of the referenced material libraries, default_material is used for
+    // triangles without or with an unknown material.
+    pub fn new_mesh_from_file_obj(
+        &'a self,
+        path: &std::path::Path,
+        default_material: &'a Material<'a>,
+    ) -> Result<NodeBuilder<'a>, crate::obj_loader::ObjError> {
+        let file = std::fs::File::open(path).map_err(crate::obj_loader::ObjError::IoError)?;
+        let reader = std::io::BufReader::new(file);
+        let mesh = crate::obj_loader::load_obj_mesh(reader)?;
+        let directory = path.parent().unwrap_or(std::path::Path::new(""));
+
+        let mut mtl_materials = HashMap::new();
+        for library in &mesh.material_libraries {
+            let library_path = directory.join(library);
+            let loaded = std::fs::File::open(&library_path)
+                .map_err(crate::obj_loader::ObjError::IoError)
+                .and_then(|f| crate::mtl_loader::load_mtl(std::io::BufReader::new(f)));
+            match loaded {
+                Ok(materials) => mtl_materials.extend(materials),
+                Err(err) => eprintln!("Skipping material library {}: {err}", library_path.display()),
+            }
+        }
+        let mut textures = HashMap::new();
+        let materials = mtl_materials
+            .iter()
+            .map(|(name, mtl)| {
+                let material = self.material_from_mtl(mtl, directory, &mut textures);
+                (name.as_str(), material)
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(self.new_mesh(mesh.segments.iter().flat_map(|segment| {
+            let material = segment
+                .material_name
+                .as_deref()
+                .and_then(|name| materials.get(name).copied())
+                .unwrap_or(default_material);
+            mesh.triangles[segment.triangles.clone()]
+                .iter()
+                .map(move |t| (*t, material))
+        })))
+    }
+    // The default scene of a .gltf or .glb file, see gltf_loader.
+    pub fn new_scene_from_file_gltf(
+        &'a self,
+        path: &std::path::Path,
+    ) -> Result<crate::gltf_loader::GltfScene<'a>, gltf::Error> {
+        crate::gltf_loader::load_gltf(self, path)
+    }
+
+    // Emitters become lights and materials with the physically based extension principled. Of
+    // the others transparent materials become glass, mostly specular ones metal and everything
+    // else diffuse.
+    fn material_from_mtl(
+        &'a self,
+        mtl: &crate::mtl_loader::MtlMaterial,
+        directory: &std::path::Path,
+        textures: &mut HashMap<std::path::PathBuf, Option<&'a Texture<'a>>>,
+    ) -> &'a Material<'a> {
+        if mtl.emission.max_component() > 0.0 {
+            return self.material_diffuse_light_solid(mtl.emission);
+        }
+        let index_of_refraction = if mtl.index_of_refraction > 1.0 {
+            mtl.index_of_refraction
+        } else {
+            1.5
+        };
+        let mut diffuse_texture = || {
+            let map = mtl.diffuse_map.as_ref()?;
+            let path = directory.join(map);
+            *textures.entry(path.clone()).or_insert_with(|| {
+                self.texture_image_from_path(&path, true)
+                    .map_err(|err| eprintln!("Skipping texture {}: {err}", path.display()))
+                    .ok()
+            })
+        };
+        if mtl.is_physically_based() {
+            let base_color = diffuse_texture().unwrap_or_else(|| self.texture_solid(mtl.diffuse));
+            let defaults = self.principled(base_color);
+            let scalar =
+                |value: Option<f32>, default| value.map_or(default, |v| self.texture_scalar(v));
+            return self.material_principled(Principled {
+                metallic: scalar(mtl.metallic, defaults.metallic),
+                roughness: scalar(mtl.roughness, defaults.roughness),
+                sheen: scalar(mtl.sheen, defaults.sheen),
+                clearcoat: scalar(mtl.clearcoat, defaults.clearcoat),
+                clearcoat_roughness: scalar(mtl.clearcoat_roughness, defaults.clearcoat_roughness),
+                transmission: self.texture_scalar(1.0 - mtl.dissolve),
+                index_of_refraction,
+                ..defaults
+            });
+        }
+        let transparent = mtl.dissolve < 1.0 || matches!(mtl.illumination_model, Some(4 | 6 | 7 | 9));
+        if transparent {
+            return self.material_dielectric(index_of_refraction);
+        }
+        if mtl.specular.max_component() > mtl.diffuse.max_component() && mtl.diffuse_map.is_none() {
+            // Phong exponent to roughness
+            let fuzz = (2.0 / (mtl.specular_exponent + 2.0)).sqrt();
+            return self.material_metal_solid(mtl.specular, fuzz);
+        }
+        match diffuse_texture() {
+            Some(texture) => self.material_lambert(texture),
+            None => self.material_lambert_solid(mtl.diffuse),
+        }
     }
 }
 
 struct Node<'a> {
     geo: Vec<(Geometry, &'a Material<'a>, bool, f32)>,
+    // Geometry, materials, material index per triangle and whether it is a point of interest
+    meshes: Vec<(&'a MeshGeometry, &'a [&'a Material<'a>], &'a [u32], bool)>,
     transformation: Transformation,
     moving_animation: Dir3,
     children: Vec<NodeRef<'a>>,
@@ -235,6 +627,9 @@ impl<'a> NodeBuilder<'a> {
         for geo in &mut self.0.geo {
             geo.2 = true;
         }
+        for mesh in &mut self.0.meshes {
+            mesh.3 = true;
+        }
         self
     }
 
@@ -250,12 +645,30 @@ impl<'a> NodeBuilder<'a> {
         self.0.transformation = self.0.transformation.translate(offset);
         self
     }
+    pub fn scale(mut self, factors: Dir3) -> Self {
+        self.0.transformation = self.0.transformation.scale(factors);
+        self
+    }
+    pub fn scale_uniform(mut self, factor: f32) -> Self {
+        self.0.transformation = self.0.transformation.scale_uniform(factor);
+        self
+    }
+    pub fn rotate_axis(mut self, axis: Dir3, angle: f32) -> Self {
+        self.0.transformation = self.0.transformation.rotate_axis(axis, angle);
+        self
+    }
+    pub fn look_at(mut self, target: Point3, up: Dir3) -> Self {
+        self.0.transformation = self.0.transformation.look_at(target, up);
+        self
+    }
     pub fn animate_moving(mut self, velocity: Dir3) -> Self {
         self.0.moving_animation += velocity;
         self
     }
     pub fn set_all_geo_densitity(mut self, densitity: f32) -> Self {
-        if (0.0..1.0).contains(&densitity) {
+        if !self.0.meshes.is_empty() {
+            panic!("Meshes can't be volumes")
+        } else if (0.0..1.0).contains(&densitity) {
             for geo in &mut self.0.geo {
                 geo.3 = densitity;
             }
@@ -276,32 +689,95 @@ impl<'a> NodeRef<'a> {
         background: BackgroundColor,
         camera: Camera,
     ) -> World<'a> {
+        let mut use_counts = HashMap::new();
+        self.count_uses(&mut use_counts);
+        let mut ctx = FinishContext {
+            wb,
+            time_range: camera.time_interval.clone(),
+            use_counts,
+            instances: HashMap::new(),
+            scattering_distribution_providers: Vec::new(),
+        };
         let mut all_elements = Vec::new();
-        let scattering_distribution_provider =
-            self.finish_internal(wb, &camera.time_interval, &Transformation::ZERO, &mut all_elements);
-        let root = wb.alloc(SceneElement::BoundingVolumeHierarchy(BoundingVolumeHierarchy::new(all_elements, &camera.time_interval)));
+        self.place(&mut ctx, &Transformation::ZERO, Dir3::ZERO, &mut all_elements);
+        let root = ctx.new_bvh(all_elements);
         let hittable = wb.alloc(Scene::new(root));
         World {
             background,
             camera,
             hittable,
-            scattering_distribution_provider,
+            scattering_distribution_provider: ctx.scattering_distribution_provider(),
         }
     }
-    fn finish_internal(
+
+    fn key(&self) -> *const Node<'a> {
+        Rc::as_ptr(&self.0)
+    }
+
+    // Counts how many parents reference each node, every node is only descended into once.
+    fn count_uses(&self, use_counts: &mut HashMap<*const Node<'a>, usize>) {
+        let count = use_counts.entry(self.key()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            for child in &self.0.children {
+                child.count_uses(use_counts);
+            }
+        }
+    }
+
+    // Adds the node to the parent, nodes used more than once are added as instance of a shared
+    // bottom level hierarchy, all others are flattened into the parent.
+    fn place(
         &self,
-        wb: &'a WorldBuilder<'a>,
-        time_range: &Range<f32>,
+        ctx: &mut FinishContext<'a>,
         parent_transform: &Transformation,
-        result : &mut Vec<&'a SceneElement<'a>>
-    ) -> 
-        Option<WorldScatteringDistributionProvider>
-     {
-        let full_trans = parent_transform.then(&self.0.transformation);
-        let mut wsd = None;
+        parent_velocity: Dir3,
+        result: &mut Vec<&'a SceneElement<'a>>,
+    ) {
+        // The own transformation is applied first, then the ones of the parents.
+        let full_trans = self.0.transformation.then(parent_transform);
+        let velocity = parent_velocity + self.0.moving_animation;
+        if ctx.use_counts[&self.key()] > 1 {
+            let mut elem = self.instance(ctx);
+            if !full_trans.is_zero() {
+                elem = ctx.wb.alloc(SceneElement::Transformation(elem, full_trans));
+            }
+            if velocity != Dir3::ZERO {
+                elem = ctx.wb.alloc(SceneElement::Animation(elem, velocity));
+            }
+            result.push(elem);
+            self.collect_scattering_distribution_provider(ctx, &full_trans);
+        } else {
+            self.flatten(ctx, &full_trans, velocity, result);
+        }
+    }
+
+    // The bottom level hierarchy of the node in its own coordinate system, shared by all instances.
+    fn instance(&self, ctx: &mut FinishContext<'a>) -> &'a SceneElement<'a> {
+        if let Some(elem) = ctx.instances.get(&self.key()) {
+            return elem;
+        }
+        let mut elements = Vec::new();
+        // Points of interest are collected per instance in world space.
+        let provider_count = ctx.scattering_distribution_providers.len();
+        self.flatten(ctx, &Transformation::ZERO, Dir3::ZERO, &mut elements);
+        ctx.scattering_distribution_providers.truncate(provider_count);
+        let elem = ctx.new_bvh(elements);
+        ctx.instances.insert(self.key(), elem);
+        elem
+    }
+
+    fn flatten(
+        &self,
+        ctx: &mut FinishContext<'a>,
+        full_trans: &Transformation,
+        velocity: Dir3,
+        result: &mut Vec<&'a SceneElement<'a>>,
+    ) {
+        let wb = ctx.wb;
         for (geo, material, is_poi, densitity) in &self.0.geo {
             let (real_geo, remaining_transformation) =
-                geo.partial_apply_transformation(&full_trans);
+                geo.partial_apply_transformation(full_trans);
             let mut elem = wb.alloc(if *densitity < 1.0 {
                 SceneElement::VolumeGeometry(VolumeGeometry::new(real_geo, material, *densitity))
             } else {
@@ -310,20 +786,149 @@ impl<'a> NodeRef<'a> {
             if let Some(trans) = remaining_transformation {
                 elem = wb.alloc(SceneElement::Transformation(elem, trans))
             }
-            if self.0.moving_animation != Dir3::ZERO {
-                elem = wb.alloc(SceneElement::Animation(elem, self.0.moving_animation))
+            if velocity != Dir3::ZERO {
+                elem = wb.alloc(SceneElement::Animation(elem, velocity))
             }
             result.push(elem);
-            if *is_poi && remaining_transformation.is_none() && wsd.is_none() {
-                wsd = real_geo.get_world_scattering_provider()
+            if *is_poi {
+                ctx.add_scattering_distribution_provider(
+                    real_geo,
+                    remaining_transformation,
+                    material,
+                );
+            }
+        }
+        for &(geometry, materials, material_ids, _) in &self.0.meshes {
+            // Shared meshes are only transformed as a whole
+            let mut elem = wb.alloc(SceneElement::Mesh(Mesh::new(
+                geometry,
+                materials,
+                material_ids,
+                wb.bvh_strategy,
+            )));
+            if !full_trans.is_zero() {
+                elem = wb.alloc(SceneElement::Transformation(elem, *full_trans));
             }
+            if velocity != Dir3::ZERO {
+                elem = wb.alloc(SceneElement::Animation(elem, velocity))
+            }
+            result.push(elem);
         }
+        self.collect_mesh_scattering_distribution_providers(ctx, full_trans);
         for child in &self.0.children {
-            let child_wsd = child.finish_internal(wb, time_range, &full_trans, result);
-            if wsd.is_none() && child_wsd.is_some() {
-                wsd = child_wsd;
+            child.place(ctx, full_trans, velocity, result);
+        }
+    }
+
+    fn collect_scattering_distribution_provider(
+        &self,
+        ctx: &mut FinishContext<'a>,
+        full_trans: &Transformation,
+    ) {
+        for (geo, material, is_poi, _) in &self.0.geo {
+            if *is_poi {
+                let (real_geo, remaining_transformation) =
+                    geo.partial_apply_transformation(full_trans);
+                ctx.add_scattering_distribution_provider(
+                    real_geo,
+                    remaining_transformation,
+                    material,
+                );
             }
         }
-        wsd
+        self.collect_mesh_scattering_distribution_providers(ctx, full_trans);
+        for child in &self.0.children {
+            let child_trans = child.0.transformation.then(full_trans);
+            child.collect_scattering_distribution_provider(ctx, &child_trans);
+        }
+    }
+
+    // A mesh which is a point of interest is a single provider. Its triangles are chosen by
+    // their emitted power, or by their area if none of them emits.
+    fn collect_mesh_scattering_distribution_providers(
+        &self,
+        ctx: &mut FinishContext<'a>,
+        full_trans: &Transformation,
+    ) {
+        for &(geometry, materials, material_ids, is_poi) in &self.0.meshes {
+            if !is_poi {
+                continue;
+            }
+            let (areas, powers): (Vec<f32>, Vec<f32>) = (0..geometry.triangle_count())
+                .map(|id| {
+                    let material = materials[material_ids.get(id).map_or(0, |&m| m as usize)];
+                    let [p0, p1, p2] = geometry
+                        .triangle_positions(id)
+                        .map(|p| full_trans.apply_point(p));
+                    let area = 0.5 * Dir3::cross(p1 - p0, p2 - p0).length();
+                    (area, area * material.average_emission().luminance())
+                })
+                .unzip();
+            let emits = powers.iter().any(|&power| power > 0.0);
+            let weights = if emits { powers } else { areas };
+            if let Some(geo) =
+                MeshSamplingGeometry::new(geometry, full_trans, &weights, ctx.wb.bvh_strategy)
+            {
+                ctx.scattering_distribution_providers.push((
+                    WorldScatteringDistributionProvider::Mesh(geo),
+                    weights.iter().sum(),
+                    emits,
+                ));
+            }
+        }
+    }
+}
+
+struct FinishContext<'a> {
+    wb: &'a WorldBuilder<'a>,
+    time_range: Range<f32>,
+    use_counts: HashMap<*const Node<'a>, usize>,
+    instances: HashMap<*const Node<'a>, &'a SceneElement<'a>>,
+    // Every point of interest with its weight within its group and whether it emits light
+    scattering_distribution_providers: Vec<(WorldScatteringDistributionProvider, f32, bool)>,
+}
+
+impl<'a> FinishContext<'a> {
+    fn new_bvh(&self, elements: Vec<&'a SceneElement<'a>>) -> &'a SceneElement<'a> {
+        self.wb.alloc(SceneElement::BoundingVolumeHierarchy(
+            BoundingVolumeHierarchy::new(elements, &self.time_range, self.wb.bvh_strategy),
+        ))
+    }
+
+    fn add_scattering_distribution_provider(
+        &mut self,
+        geo: Geometry,
+        transformation: Option<Transformation>,
+        material: &Material,
+    ) {
+        if let Some(mut provider) = geo.get_world_scattering_provider() {
+            if let Some(trans) = transformation {
+                provider =
+                    WorldScatteringDistributionProvider::Transformation(Box::new(provider), trans);
+            }
+            // Lights are weighted by their emitted power, other points of interest by their area
+            let area = provider.area();
+            let power = area * material.average_emission().luminance();
+            let emits = power > 0.0;
+            let weight = if emits { power } else { area };
+            self.scattering_distribution_providers.push((provider, weight, emits));
+        }
+    }
+
+    // The mixture of the lights among the points of interest, weighted by their power. Shadow
+    // rays towards other points of interest find no light, so they are only sampled, by their
+    // area, if there are no lights.
+    fn scattering_distribution_provider(self) -> Option<WorldScatteringDistributionProvider> {
+        let sampled_lights = self
+            .scattering_distribution_providers
+            .iter()
+            .any(|(_, weight, emits)| *emits && *weight > 0.0);
+        WorldScatteringDistributionProvider::new_list(
+            self.scattering_distribution_providers
+                .into_iter()
+                .filter(|(_, _, emits)| *emits == sampled_lights)
+                .map(|(provider, weight, _)| (provider, weight))
+                .collect(),
+        )
     }
 }
@@ -1,6 +1,10 @@
 use std::ops::Range;
 
+use rand::Rng;
+
 use crate::{
+    common,
+    hittable::rect_geometry::{RectGeometry, RectPlane},
     math,
     ray::Ray,
     vec3::{Dir3, Point3},
@@ -34,6 +38,10 @@ impl Aabb {
             max: Point3::new_from_arr(max),
         }
     }
+    pub fn new_surrounding_boxes_iter<TIter: Iterator<Item = Aabb>>(mut boxes: TIter) -> Self {
+        let first = boxes.next().expect("At least one box is required");
+        boxes.fold(first, |a, b| Aabb::new_surrounding_boxes(&[a, b]))
+    }
     pub fn new_surrounding_maybe_boxes_iter<TIter : Iterator<Item=Option<Aabb>>>(boxes: TIter) -> Option<Self> {
         let mut result = Option::<Aabb>::None;
         for bounding_box in boxes {
@@ -61,16 +69,46 @@ impl Aabb {
             max: Point3::new_from_arr(max),
         }
     }
+    pub fn new_surrounding_points_iter<TIter: Iterator<Item = Point3>>(mut points: TIter) -> Self {
+        let first = points.next().expect("At least one point is required");
+        points.fold(Self::new_corners(first, first), |a, p| {
+            Self::new_corners(
+                Point3::new_from_arr(math::min_array(a.min.0.e, p.0.e)),
+                Point3::new_from_arr(math::max_array(a.max.0.e, p.0.e)),
+            )
+        })
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3((self.min.0 + self.max.0) * 0.5)
+    }
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.right() * d.up() + d.up() * d.forward() + d.forward() * d.right())
+    }
 
     pub fn hit_cond(&self, ray: &Ray, t_range: &Range<f32>) -> bool {
-        for i in 0..3 {
+        self.hit_cond_inv(ray.origin, ray.direction.0.e.map(|d| 1.0 / d), t_range)
+    }
+
+    // Same as hit_cond, but with the reciprocal of the ray direction precomputed.
+    pub fn hit_cond_inv(
+        &self,
+        origin: Point3,
+        inv_direction: [f32; 3],
+        t_range: &Range<f32>,
+    ) -> bool {
+        let mut t_min = t_range.start;
+        let mut t_max = t_range.end;
+        for (i, inv_d) in inv_direction.iter().enumerate() {
             let (t0, t1) = math::minmax(
-                (self.min.0.e[i] - ray.origin.0.e[i]) / ray.direction.0.e[i],
-                (self.max.0.e[i] - ray.origin.0.e[i]) / ray.direction.0.e[i],
+                (self.min.0.e[i] - origin.0.e[i]) * inv_d,
+                (self.max.0.e[i] - origin.0.e[i]) * inv_d,
             );
-            let t_min = t0.max(t_range.start);
-            let t_max = t1.min(t_range.end);
-            if t_max <= t_min {
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            // Equality keeps boxes of zero thickness, e.g. around axis aligned triangles
+            if t_max < t_min {
                 return false;
             }
         }
@@ -92,12 +130,17 @@ impl Aabb {
 
         let mut surface_normal = Dir3::ZERO;
         surface_normal.0.e[plane] = (position.0.e[plane] - center).signum();
+        // There are no texture coordinates, any two axes of the face do
+        let (mut dpdu, mut dpdv) = (Dir3::ZERO, Dir3::ZERO);
+        dpdu.0.e[(plane + 1) % 3] = 1.0;
+        dpdv.0.e[(plane + 2) % 3] = 1.0;
         Some(GeoHitInteraction::new_from_ray(
             ray,
             &position,
             &surface_normal,
             t,
             Vec2f::ZERO,
+            (dpdu, dpdv),
         ))
     }
     pub fn intersections_line(
@@ -166,6 +209,42 @@ impl Aabb {
         Some(((near, near_plane), (far, far_plane)))
     }
 
+    pub fn faces(&self) -> [RectGeometry; 6] {
+        [RectPlane::Xy, RectPlane::Xz, RectPlane::Yz].map(|rect_plane| {
+            let (p0, p1, n) = rect_plane.get_axis();
+            let face = |dist| RectGeometry {
+                rect_plane,
+                dist,
+                r0: (self.min.0.e[p0], self.max.0.e[p0]),
+                r1: (self.min.0.e[p1], self.max.0.e[p1]),
+            };
+            [face(self.min.0.e[n]), face(self.max.0.e[n])]
+        })
+        .concat()
+        .try_into()
+        .unwrap()
+    }
+
+    // Picks a face proportional to its area and samples a point on it.
+    pub fn generate(&self, origin: Point3, rng: &mut common::TRng) -> Dir3 {
+        let faces = self.faces();
+        let mut choice = rng.gen::<f32>() * self.surface_area();
+        for face in &faces {
+            choice -= face.area();
+            if choice < 0.0 {
+                return face.generate(origin, rng);
+            }
+        }
+        faces[5].generate(origin, rng)
+    }
+    pub fn value(&self, origin: Point3, direction: Dir3) -> f32 {
+        let total_area = self.surface_area();
+        self.faces()
+            .iter()
+            .map(|face| face.area() / total_area * face.value(origin, direction))
+            .sum()
+    }
+
     pub fn translate(&self, offset: Dir3) -> Aabb {
         Self::new_corners(self.min + offset, self.max + offset)
     }
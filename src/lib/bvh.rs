@@ -0,0 +1,299 @@
+use std::ops::Range;
+
+use crate::{aabb::Aabb, ray::Ray};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BvhStrategy {
+    // Sort by the lower corner along a round robin axis and split at the median.
+    Median,
+    // Binned surface area heuristic over the centroid bounds, leaves hold up to max_leaf_size items.
+    SurfaceAreaHeuristic {
+        bin_count: usize,
+        max_leaf_size: usize,
+    },
+}
+
+impl BvhStrategy {
+    pub const SAH: BvhStrategy = BvhStrategy::SurfaceAreaHeuristic {
+        bin_count: 12,
+        max_leaf_size: 4,
+    };
+}
+
+impl Default for BvhStrategy {
+    fn default() -> Self {
+        Self::SAH
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+struct BvhNode {
+    aabb: Aabb,
+    axis_id: usize,
+    // Inner node: index of the right child, the left child directly follows the node.
+    // Leaf: index of the first item in item_ids.
+    offset: usize,
+    // Zero for inner nodes.
+    item_count: usize,
+}
+
+// Relative cost of visiting an inner node compared to intersecting an item.
+const TRAVERSAL_COST: f32 = 0.125;
+const MAX_DEPTH: usize = 64;
+
+// The pure tree part of a bounding volume hierarchy, items are referenced by their index.
+pub struct BvhTree {
+    nodes: Vec<BvhNode>,
+    item_ids: Vec<usize>,
+}
+
+impl BvhTree {
+    pub fn new(items: &[(usize, Aabb)], strategy: BvhStrategy) -> Self {
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * items.len()),
+            item_ids: Vec::with_capacity(items.len()),
+        };
+        if !items.is_empty() {
+            let mut items = items.to_vec();
+            match strategy {
+                BvhStrategy::Median => tree.build_median(&mut items, 0, 0),
+                BvhStrategy::SurfaceAreaHeuristic {
+                    bin_count,
+                    max_leaf_size,
+                } => tree.build_sah(&mut items, bin_count.max(2), max_leaf_size.max(1), 0),
+            };
+        }
+        tree
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.aabb)
+    }
+
+    fn push_leaf(&mut self, items: &[(usize, Aabb)], aabb: Aabb) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            axis_id: 0,
+            offset: self.item_ids.len(),
+            item_count: items.len(),
+        });
+        self.item_ids.extend(items.iter().map(|(id, _)| *id));
+        id
+    }
+
+    fn surrounding(items: &[(usize, Aabb)]) -> Aabb {
+        Aabb::new_surrounding_boxes_iter(items.iter().map(|(_, aabb)| *aabb))
+    }
+
+    fn build_median(&mut self, items: &mut [(usize, Aabb)], axis_id: usize, depth: usize) -> usize {
+        let aabb = Self::surrounding(items);
+        if items.len() == 1 || depth >= MAX_DEPTH {
+            return self.push_leaf(items, aabb);
+        }
+        items.sort_by(|a, b| {
+            a.1.min.0.e[axis_id]
+                .partial_cmp(&b.1.min.0.e[axis_id])
+                .unwrap()
+        });
+        let result_id = self.nodes.len();
+        self.nodes.push(Default::default());
+        let (left_half, right_half) = items.split_at_mut(items.len() / 2);
+        let left_axis_id = (axis_id + left_half.len()) % 3;
+        let right_axis_id = (axis_id + right_half.len()) % 3;
+        self.build_median(left_half, left_axis_id, depth + 1);
+        let right_id = self.build_median(right_half, right_axis_id, depth + 1);
+        self.nodes[result_id] = BvhNode {
+            aabb,
+            axis_id,
+            offset: right_id,
+            item_count: 0,
+        };
+        result_id
+    }
+
+    fn build_sah(
+        &mut self,
+        items: &mut [(usize, Aabb)],
+        bin_count: usize,
+        max_leaf_size: usize,
+        depth: usize,
+    ) -> usize {
+        let aabb = Self::surrounding(items);
+        if items.len() == 1 || depth >= MAX_DEPTH {
+            return self.push_leaf(items, aabb);
+        }
+
+        let centroid_bounds =
+            Aabb::new_surrounding_points_iter(items.iter().map(|(_, aabb)| aabb.centroid()));
+        let best_split = (0..3)
+            .filter_map(|axis_id| {
+                Self::best_bin_split(items, &centroid_bounds, axis_id, bin_count)
+                    .map(|(bin, cost)| (axis_id, bin, cost))
+            })
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+        let leaf_cost = items.len() as f32;
+        let (axis_id, mid) = match best_split {
+            Some((axis_id, bin, cost)) => {
+                let split_cost = TRAVERSAL_COST + cost / aabb.surface_area().max(f32::EPSILON);
+                if items.len() <= max_leaf_size && leaf_cost <= split_cost {
+                    return self.push_leaf(items, aabb);
+                }
+                let (low, scale) = Self::bin_mapping(&centroid_bounds, axis_id, bin_count);
+                let mid = partition(items, |(_, b)| {
+                    Self::bin_id(b.centroid().0.e[axis_id], low, scale, bin_count) < bin
+                });
+                (axis_id, mid)
+            }
+            None => {
+                // All centroids coincide, no bin split can separate the items.
+                if items.len() <= max_leaf_size {
+                    return self.push_leaf(items, aabb);
+                }
+                (0, items.len() / 2)
+            }
+        };
+
+        let result_id = self.nodes.len();
+        self.nodes.push(Default::default());
+        let (left_half, right_half) = items.split_at_mut(mid);
+        self.build_sah(left_half, bin_count, max_leaf_size, depth + 1);
+        let right_id = self.build_sah(right_half, bin_count, max_leaf_size, depth + 1);
+        self.nodes[result_id] = BvhNode {
+            aabb,
+            axis_id,
+            offset: right_id,
+            item_count: 0,
+        };
+        result_id
+    }
+
+    fn bin_mapping(centroid_bounds: &Aabb, axis_id: usize, bin_count: usize) -> (f32, f32) {
+        let low = centroid_bounds.min.0.e[axis_id];
+        let extent = centroid_bounds.max.0.e[axis_id] - low;
+        (low, bin_count as f32 / extent)
+    }
+
+    fn bin_id(value: f32, low: f32, scale: f32, bin_count: usize) -> usize {
+        (((value - low) * scale) as usize).min(bin_count - 1)
+    }
+
+    // Returns the first bin of the right side and the unnormalized cost of the split.
+    fn best_bin_split(
+        items: &[(usize, Aabb)],
+        centroid_bounds: &Aabb,
+        axis_id: usize,
+        bin_count: usize,
+    ) -> Option<(usize, f32)> {
+        let extent = centroid_bounds.max.0.e[axis_id] - centroid_bounds.min.0.e[axis_id];
+        if extent <= 0.0 {
+            return None;
+        }
+        let (low, scale) = Self::bin_mapping(centroid_bounds, axis_id, bin_count);
+        let mut bins = vec![(0usize, Option::<Aabb>::None); bin_count];
+        for (_, aabb) in items {
+            let bin = &mut bins[Self::bin_id(aabb.centroid().0.e[axis_id], low, scale, bin_count)];
+            bin.0 += 1;
+            bin.1 = merge_maybe(bin.1, Some(*aabb));
+        }
+
+        // right_costs[i] is the cost of the bins i..bin_count
+        let mut right_costs = vec![0.0; bin_count];
+        let mut count = 0;
+        let mut bounds = Option::<Aabb>::None;
+        for i in (1..bin_count).rev() {
+            count += bins[i].0;
+            bounds = merge_maybe(bounds, bins[i].1);
+            right_costs[i] = count as f32 * bounds.map_or(0.0, |b| b.surface_area());
+        }
+
+        let mut best = Option::<(usize, f32)>::None;
+        let mut count = 0;
+        let mut bounds = Option::<Aabb>::None;
+        for i in 1..bin_count {
+            count += bins[i - 1].0;
+            bounds = merge_maybe(bounds, bins[i - 1].1);
+            if count == 0 || count == items.len() {
+                continue;
+            }
+            let cost = count as f32 * bounds.map_or(0.0, |b| b.surface_area()) + right_costs[i];
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((i, cost));
+            }
+        }
+        best
+    }
+
+    // Calls hit_item for every item whose leaf is hit by the ray. hit_item must shrink
+    // t_range.end to the distance of the hit it returns, the closest hit is returned.
+    pub fn hit<T, F: FnMut(usize, &mut Range<f32>) -> Option<T>>(
+        &self,
+        ray: &Ray,
+        t_range: &mut Range<f32>,
+        mut hit_item: F,
+    ) -> Option<T> {
+        let inv_direction = ray.direction.0.e.map(|d| 1.0 / d);
+        if !self
+            .nodes
+            .first()?
+            .aabb
+            .hit_cond_inv(ray.origin, inv_direction, t_range)
+        {
+            return None;
+        }
+        let mut closest = None;
+        let mut stack = [0usize; 2 * MAX_DEPTH];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node_id = stack[stack_len];
+            let node = &self.nodes[node_id];
+            if node.item_count > 0 {
+                for id in &self.item_ids[node.offset..node.offset + node.item_count] {
+                    if let Some(hi) = hit_item(*id, t_range) {
+                        closest = Some(hi);
+                    }
+                }
+            } else {
+                // Visit the side first in which the ray points (i.e. ray points from left to right -> first check left, then right)
+                // We still have to check both sides, so we can correctly handle elements on the seperator
+                let (first, second) = if ray.direction.0.e[node.axis_id] > 0.0 {
+                    (node_id + 1, node.offset)
+                } else {
+                    (node.offset, node_id + 1)
+                };
+                for child in [second, first] {
+                    if self.nodes[child]
+                        .aabb
+                        .hit_cond_inv(ray.origin, inv_direction, t_range)
+                    {
+                        stack[stack_len] = child;
+                        stack_len += 1;
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn merge_maybe(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Aabb::new_surrounding_boxes(&[a, b])),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// Moves all elements matching the predicate to the front, returns the count of matching elements.
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], pred: F) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
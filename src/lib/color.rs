@@ -55,6 +55,14 @@ impl Color {
         Self::new_rgb(self.0.e[0].sqrt(), self.0.e[1].sqrt(), self.0.e[2].sqrt())
     }
 
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.0.e[0] + 0.7152 * self.0.e[1] + 0.0722 * self.0.e[2]
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.0.e[0].max(self.0.e[1]).max(self.0.e[2])
+    }
+
     pub fn convolution(a: Self, b: Self) -> Self {
         Self::new_rgb(
             a.0.e[0] * b.0.e[0],
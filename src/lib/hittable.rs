@@ -4,6 +4,8 @@ use rand::Rng;
 
 use crate::{
     aabb::Aabb,
+    bvh::{BvhStrategy, BvhTree},
+    color::Color,
     common,
     material::Material,
     ray::Ray,
@@ -13,19 +15,24 @@ use crate::{
     WorldScatteringDistributionProvider,
 };
 
+pub mod mesh_geometry;
 pub mod rect_geometry;
 pub mod sphere_geometry;
 pub mod triangle_geometry;
 use self::{
-    rect_geometry::RectGeometry, sphere_geometry::SphereGeometry,
+    mesh_geometry::Mesh, rect_geometry::RectGeometry, sphere_geometry::SphereGeometry,
     triangle_geometry::TriangleGeometry,
 };
 
+// dpdu and dpdv are the derivatives of the position by the texture coordinates, they span the
+// tangent plane but are neither normalized nor orthogonal. They are not flipped with the normal.
 #[derive(Debug, Clone)]
 pub struct GeoHitInteraction {
     pub position: Point3,
     pub normal: Dir3,
     pub uv: Vec2f,
+    pub dpdu: Dir3,
+    pub dpdv: Dir3,
     pub t: f32,
     pub front_face: bool,
 }
@@ -37,6 +44,7 @@ impl GeoHitInteraction {
         surface_normal: &Dir3,
         t: f32,
         uv: Vec2f,
+        (dpdu, dpdv): (Dir3, Dir3),
     ) -> Self {
         let front_face = Dir3::dot(*surface_normal, ray.direction) < 0.0;
         let normal = if front_face {
@@ -50,6 +58,8 @@ impl GeoHitInteraction {
             t,
             front_face,
             uv,
+            dpdu,
+            dpdv,
         }
     }
 
@@ -58,9 +68,12 @@ impl GeoHitInteraction {
             position: self.position,
             normal: self.normal,
             uv: self.uv,
+            dpdu: self.dpdu,
+            dpdv: self.dpdv,
             t: self.t,
             front_face: self.front_face,
             material,
+            color: Color::WHITE,
         }
     }
 }
@@ -70,9 +83,14 @@ pub struct HitInteraction<'a> {
     pub position: Point3,
     pub normal: Dir3,
     pub uv: Vec2f,
+    // See GeoHitInteraction
+    pub dpdu: Dir3,
+    pub dpdv: Dir3,
     pub t: f32,
     pub front_face: bool,
     pub material: &'a Material<'a>,
+    // The vertex color interpolated over mesh triangles, white for everything else
+    pub color: Color,
 }
 
 impl<'a> HitInteraction<'a> {
@@ -83,6 +101,7 @@ impl<'a> HitInteraction<'a> {
         t: f32,
         material: &'a Material,
         uv: Vec2f,
+        (dpdu, dpdv): (Dir3, Dir3),
     ) -> Self {
         let front_face = Dir3::dot(*surface_normal, ray.direction) < 0.0;
         let normal = if front_face {
@@ -97,6 +116,9 @@ impl<'a> HitInteraction<'a> {
             front_face,
             material,
             uv,
+            dpdu,
+            dpdv,
+            color: Color::WHITE,
         }
     }
 }
@@ -113,6 +135,7 @@ pub enum SceneElement<'a> {
     Group(Vec<&'a SceneElement<'a>>),
     BoundingVolumeHierarchy(BoundingVolumeHierarchy<'a>),
     SurfaceGeometry(Geometry, &'a Material<'a>),
+    Mesh(Mesh<'a>),
     VolumeGeometry(VolumeGeometry<'a>),
     Animation(&'a SceneElement<'a>, Dir3),
     Transformation(&'a SceneElement<'a>, Transformation),
@@ -132,7 +155,7 @@ impl<'a> Scene<'a> {
         ray: &Ray,
         t_range: &Range<f32>,
         rng: &mut rand_xoshiro::Xoroshiro128PlusPlus,
-    ) -> Option<HitInteraction> {
+    ) -> Option<HitInteraction<'a>> {
         self.root.hit(ray, t_range, rng)
     }
 }
@@ -159,7 +182,11 @@ impl Geometry {
     pub fn get_world_scattering_provider(&self) -> Option<WorldScatteringDistributionProvider> {
         match self {
             Geometry::Rect(geo) => Some(WorldScatteringDistributionProvider::Rect(*geo)),
-            _ => None,
+            Geometry::Sphere(geo) => Some(WorldScatteringDistributionProvider::Sphere(*geo)),
+            Geometry::AxisAlignedBox(geo) => {
+                Some(WorldScatteringDistributionProvider::AxisAlignedBox(*geo))
+            }
+            Geometry::Triangle(geo) => Some(WorldScatteringDistributionProvider::Triangle(*geo)),
         }
     }
     pub fn partial_apply_transformation(
@@ -168,22 +195,30 @@ impl Geometry {
     ) -> (Geometry, Option<Transformation>) {
         match self {
             Geometry::Sphere(geo) => {
-                let center = transformation.apply_point(geo.center);
-                let radius = transformation.apply_distance(geo.radius);
-                (Geometry::Sphere(SphereGeometry::new(center, radius)), None)
+                if let Some(scale) = transformation.uniform_scale() {
+                    let center = transformation.apply_point(geo.center);
+                    let radius = geo.radius * scale;
+                    (Geometry::Sphere(SphereGeometry::new(center, radius)), None)
+                } else {
+                    (*self, Some(*transformation))
+                }
             }
             Geometry::Triangle(geo) => {
                 let positions = geo.positions.map(|p| transformation.apply_point(p));
                 let normals = geo.normals.map(|n| transformation.apply_normal(n));
                 (
-                    Geometry::Triangle(TriangleGeometry {
+                    Geometry::Triangle(TriangleGeometry::new(
                         positions,
                         normals,
-                        texture_coords: geo.texture_coords,
-                    }),
+                        geo.texture_coords,
+                    )),
                     None,
                 )
             }
+            Geometry::AxisAlignedBox(geo) if transformation.is_axis_aligned_scale() => (
+                Geometry::AxisAlignedBox(transformation.apply_aabb(*geo)),
+                None,
+            ),
             Geometry::AxisAlignedBox(geo) => {
                 let (translation, remainder) = transformation.split_translation_remainder();
                 let new = Aabb::new_corners(geo.min + translation, geo.max + translation);
@@ -214,7 +249,7 @@ impl<'a> SceneElement<'a> {
         ray: &Ray,
         t_range: &Range<f32>,
         rng: &mut rand_xoshiro::Xoroshiro128PlusPlus,
-    ) -> Option<HitInteraction> {
+    ) -> Option<HitInteraction<'a>> {
         match self {
             SceneElement::Group(elements) => {
                 let mut t_range_copy = t_range.clone();
@@ -227,20 +262,23 @@ impl<'a> SceneElement<'a> {
                 }
                 closest
             }
-            SceneElement::SurfaceGeometry(geo, material) => geo
-                .hit(ray, t_range)
-                .map(|h| h.to_hit_interaction(material)),
-            SceneElement::VolumeGeometry(volume_geometry) => volume_geometry.hit(ray, t_range, rng),
-            SceneElement::Transformation(elem, transform) => {
-                let ray_transformed = transform.reverse_ray(ray);
-                elem.hit(&ray_transformed, t_range, rng)
-                    .map(|h| transform.apply_hit_interaction(h))
+            SceneElement::SurfaceGeometry(geo, material) => {
+                let mut t_range = t_range.clone();
+                loop {
+                    let hi = geo.hit(ray, &t_range)?.to_hit_interaction(material);
+                    if !material.skips_hit(&hi, rng) {
+                        return Some(hi);
+                    }
+                    // The same offset as for new rays, so that the hit isn't found again
+                    t_range.start = hi.t + 0.001;
+                }
             }
+            SceneElement::Mesh(mesh) => mesh.hit(ray, t_range, rng),
+            SceneElement::VolumeGeometry(volume_geometry) => volume_geometry.hit(ray, t_range, rng),
+            SceneElement::Transformation(elem, transform) => transform.hit(elem, ray, t_range, rng),
             SceneElement::Animation(elem, velocity) => {
                 let transform = Transformation::ZERO.translate(*velocity * ray.time);
-                let ray_transformed = transform.reverse_ray(ray);
-                elem.hit(&ray_transformed, t_range, rng)
-                    .map(|h| transform.apply_hit_interaction(h))
+                transform.hit(elem, ray, t_range, rng)
             }
             SceneElement::BoundingVolumeHierarchy(bvh) => bvh.hit(ray, t_range, rng),
         }
@@ -250,6 +288,7 @@ impl<'a> SceneElement<'a> {
         match self {
             SceneElement::Group(elements) => Aabb::new_surrounding_maybe_boxes_iter(elements.iter().map(|b| b.bounding_box(time_range))),
             SceneElement::SurfaceGeometry(geo, _) => geo.bounding_box(),
+            SceneElement::Mesh(mesh) => mesh.bounding_box(),
             SceneElement::VolumeGeometry(volume) => volume.boundary.bounding_box(),
             SceneElement::Animation(geo, velocity) => {
                 geo.bounding_box(time_range).map(|aabb| 
@@ -268,25 +307,49 @@ impl<'a> SceneElement<'a> {
 }
 
 impl Transformation {
-    pub fn reverse_ray(&self, ray: &Ray) -> Ray {
-        Ray {
-            origin: self.reverse_point(ray.origin),
-            direction: self.reverse_normal(ray.direction),
-            time: ray.time,
-        }
+    // Returns the ray in the local coordinate system and the factor by which distances
+    // along the ray are stretched, the local direction is normalized again.
+    pub fn reverse_ray(&self, ray: &Ray) -> (Ray, f32) {
+        let direction = self.reverse_direction(ray.direction);
+        let stretch = direction.length();
+        (
+            Ray {
+                origin: self.reverse_point(ray.origin),
+                direction: direction / stretch,
+                time: ray.time,
+            },
+            stretch,
+        )
     }
 
-    pub fn apply_hit_interaction<'a>(&self, mut hi: HitInteraction<'a>) -> HitInteraction<'a> {
+    pub fn apply_hit_interaction<'a>(
+        &self,
+        mut hi: HitInteraction<'a>,
+        stretch: f32,
+    ) -> HitInteraction<'a> {
         hi.position = self.apply_point(hi.position);
         hi.normal = self.apply_normal(hi.normal);
+        hi.dpdu = self.apply_direction(hi.dpdu);
+        hi.dpdv = self.apply_direction(hi.dpdv);
+        hi.t /= stretch;
         hi
     }
 
+    pub fn hit<'a>(
+        &self,
+        elem: &'a SceneElement<'a>,
+        ray: &Ray,
+        t_range: &Range<f32>,
+        rng: &mut common::TRng,
+    ) -> Option<HitInteraction<'a>> {
+        let (ray_transformed, stretch) = self.reverse_ray(ray);
+        let local_t_range = (t_range.start * stretch)..(t_range.end * stretch);
+        elem.hit(&ray_transformed, &local_t_range, rng)
+            .map(|h| self.apply_hit_interaction(h, stretch))
+    }
+
     pub fn apply_aabb(&self, aabb: Aabb) -> Aabb {
-        let corners = aabb.corners();
-        for mut c in corners {
-            self.apply_point_mut(&mut c)
-        }
+        let corners = aabb.corners().map(|c| self.apply_point(c));
         Aabb::new_surrounding_points(&corners)
     }
 }
@@ -311,7 +374,7 @@ impl<'a> VolumeGeometry<'a> {
         ray: &Ray,
         t_range: &Range<f32>,
         rng: &mut common::TRng,
-    ) -> Option<HitInteraction> {
+    ) -> Option<HitInteraction<'a>> {
         let start_boundary = self
             .boundary
             .hit(ray, &(f32::NEG_INFINITY..f32::INFINITY))?
@@ -334,147 +397,85 @@ impl<'a> VolumeGeometry<'a> {
             position: ray.at(t),
             normal: Dir3::UP, // Arbitrary
             uv: Vec2f::ZERO,  // Undefined
+            dpdu: Dir3::RIGHT,
+            dpdv: Dir3::BACKWARD,
             t,
             front_face: false, // Arbitrary
             material: self.phase_function,
+            color: Color::WHITE,
         })
     }
 }
 
 
-#[derive(Default, Debug, Clone, Copy)]
-struct BoundingVolumeNode {
-    aabb: Aabb,
-    axis_id: usize,
-    left: usize,
-    right: usize,
-}
 pub struct BoundingVolumeHierarchy<'a> {
     items: Vec<&'a SceneElement<'a>>,
-    unbounded_items : Vec<usize>,
-    nodes: Vec<BoundingVolumeNode>,
-    initial_index: usize,
+    unbounded_items: Vec<usize>,
+    tree: BvhTree,
 }
 
 impl<'a> BoundingVolumeHierarchy<'a> {
-    pub fn new(items: Vec<&'a SceneElement<'a>>, time_range: &Range<f32>) -> Self {
+    pub fn new(
+        items: Vec<&'a SceneElement<'a>>,
+        time_range: &Range<f32>,
+        strategy: BvhStrategy,
+    ) -> Self {
         let hittables = items
             .iter()
             .map(|h| h.bounding_box(time_range))
             .enumerate()
-            .map(|mut x| {
-                x.0 = usize::MAX - x.0;
-                x
-            })
             .collect::<Vec<_>>();
-        let mut bounded_items = hittables.iter().filter_map(|(id, aabb)| aabb.map(|b| (*id, b))).collect::<Vec<_>>();
-        let unbounded_items = hittables.iter().filter_map(|(id, aabb)| if aabb.is_none() { Some(*id) } else { None }).collect::<Vec<_>>();
-        let mut nodes = Vec::<BoundingVolumeNode>::new();
-        let (initial_index, _, _) = Self::_new(&mut bounded_items[..], &mut nodes, 0, 0);
+        let bounded_items = hittables
+            .iter()
+            .filter_map(|(id, aabb)| aabb.map(|b| (*id, b)))
+            .collect::<Vec<_>>();
+        let unbounded_items = hittables
+            .iter()
+            .filter_map(|(id, aabb)| if aabb.is_none() { Some(*id) } else { None })
+            .collect::<Vec<_>>();
+        let tree = BvhTree::new(&bounded_items, strategy);
         Self {
             items,
             unbounded_items,
-            nodes,
-            initial_index,
+            tree,
         }
     }
 
-    fn _new(
-        hittables: &mut [(usize, Aabb)],
-        nodes: &mut Vec<BoundingVolumeNode>,
-        axis_id: usize,
-        depth: usize,
-    ) -> (usize, Aabb, usize) {
-        if hittables.len() == 1 {
-            (hittables[0].0, hittables[0].1, depth)
-        } else {
-            let comparer =
-                |a: &Aabb, b: &Aabb| a.min.0.e[axis_id].partial_cmp(&b.min.0.e[axis_id]).unwrap();
-            hittables.sort_by(|a, b| comparer(&a.1, &b.1));
-            if hittables.len() == 2 {
-                let result_id = nodes.len();
-                let aabb = Aabb::new_surrounding_boxes(&[hittables[0].1, hittables[1].1]);
-                nodes.push(BoundingVolumeNode {
-                    aabb,
-                    left: hittables[0].0,
-                    right: hittables[1].0,
-                    axis_id,
-                });
-                (result_id, aabb, depth)
-            } else {
-                let mid = hittables.len() / 2;
-                let (left_half, right_half) = hittables.split_at_mut(mid);
-                let result_id = nodes.len();
-                nodes.push(Default::default());
-                let (left_id, left_aabb, left_depth) =
-                    Self::_new(left_half, nodes, (axis_id + left_half.len()) % 3, depth + 1);
-                let (right_id, right_aabb, right_depth) = Self::_new(
-                    right_half,
-                    nodes,
-                    (axis_id + right_half.len()) % 3,
-                    depth + 1,
-                );
-                let aabb = Aabb::new_surrounding_boxes(&[left_aabb, right_aabb]);
-                nodes[result_id] = BoundingVolumeNode {
-                    aabb,
-                    left: left_id,
-                    right: right_id,
-                    axis_id,
-                };
-                (result_id, aabb, left_depth.max(right_depth))
-            }
-        }
-    }
-
-    fn hit_recursive(
-        &self,
-        node: usize,
+    fn hit_item(
+        &'a self,
+        id: usize,
         ray: &Ray,
         t_range: &mut Range<f32>,
         rng: &mut common::TRng,
-    ) -> Option<HitInteraction> {
-        if node > self.items.len() {
-            self.items[usize::MAX - node].hit(ray, t_range, rng)
-        } else if self.nodes[node].aabb.hit_cond(ray, t_range) {
-            // Check the sides in the order in which the ray points(i.e. ray points from left to right -> first check left, then right)
-            // We still have to check both sides, so we can correctly handle elements on the seperator
-            let order = if ray.direction.0.e[self.nodes[node].axis_id] > 0.0 {
-                [self.nodes[node].left, self.nodes[node].right]
-            } else {
-                [self.nodes[node].right, self.nodes[node].left]
-            };
-
-            self.hit_index_list(&order, ray, t_range, rng)
-        } else {
-            None
-        }
-    }
-
-    fn hit_index_list(&self, order: &[usize], ray: &Ray, t_range: &mut Range<f32>, rng: &mut rand_xoshiro::Xoroshiro128PlusPlus) -> Option<HitInteraction> {
-        let mut interaction = None;
-        for id in order {
-            if let Some(hi) = self.hit_recursive(*id, ray, t_range, rng) {
-                t_range.end = hi.t;
-                interaction = Some(hi);
-            }
-        }
-        interaction
+    ) -> Option<HitInteraction<'a>> {
+        let hi = self.items[id].hit(ray, t_range, rng)?;
+        t_range.end = hi.t;
+        Some(hi)
     }
 
     pub fn hit(
-        &self,
+        &'a self,
         ray: &Ray,
         t_range: &Range<f32>,
         rng: &mut common::TRng,
-    ) -> Option<HitInteraction> {
+    ) -> Option<HitInteraction<'a>> {
         let mut t_range = t_range.clone();
-        let closest: Option<HitInteraction> = self.hit_index_list(&self.unbounded_items[..], ray, &mut t_range, rng);
-        self.hit_recursive(self.initial_index, ray, &mut t_range, rng).or(closest)
+        let mut closest = None;
+        for id in &self.unbounded_items {
+            if let Some(hi) = self.hit_item(*id, ray, &mut t_range, rng) {
+                closest = Some(hi);
+            }
+        }
+        self.tree
+            .hit(ray, &mut t_range, |id, t_range| {
+                self.hit_item(id, ray, t_range, rng)
+            })
+            .or(closest)
     }
 
     pub fn bounding_box(&self, _time_range: &Range<f32>) -> Option<Aabb> {
-        if self.unbounded_items.len() == 0 && self.initial_index < self.nodes.len() {
-            Some(self.nodes[self.initial_index].aabb)
+        if self.unbounded_items.is_empty() {
+            self.tree.bounding_box()
         } else {
             None
         }
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, ops::Range};
+
+use rand::Rng;
+
+use crate::{
+    bvh::{BvhStrategy, BvhTree},
+    common,
+    material::Material,
+    math, Aabb, Color, Dir3, GeoHitInteraction, HitInteraction, Point3, Ray, Transformation, Vec2f,
+};
+
+use super::triangle_geometry::{intersect, position_derivatives, uniform_point, TriangleGeometry};
+
+// Triangles sharing their vertices, every triangle holds three indices into the vertex arrays.
+#[derive(Debug, Clone, Default)]
+pub struct MeshGeometry {
+    pub positions: Vec<Point3>,
+    // One per position, or empty for flat shading
+    pub normals: Vec<Dir3>,
+    // One per position, or empty
+    pub texture_coords: Vec<Vec2f>,
+    // One per position, or empty
+    pub colors: Vec<Color>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl MeshGeometry {
+    // Vertices equal in position, normal and texture coordinates are merged.
+    pub fn from_triangles<'t, I: IntoIterator<Item = &'t TriangleGeometry>>(triangles: I) -> Self {
+        let mut mesh = MeshGeometry::default();
+        let mut vertex_ids = HashMap::new();
+        for triangle in triangles {
+            let ids = [0, 1, 2].map(|i| {
+                let (p, n, uv) = (
+                    triangle.positions[i],
+                    triangle.normals[i],
+                    triangle.texture_coords[i],
+                );
+                let key = (
+                    p.0.e.map(f32::to_bits),
+                    n.0.e.map(f32::to_bits),
+                    [uv.x.to_bits(), uv.y.to_bits()],
+                );
+                *vertex_ids.entry(key).or_insert_with(|| {
+                    mesh.positions.push(p);
+                    mesh.normals.push(n);
+                    mesh.texture_coords.push(uv);
+                    mesh.positions.len() as u32 - 1
+                })
+            });
+            mesh.indices.push(ids);
+        }
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn triangle_positions(&self, id: usize) -> [Point3; 3] {
+        self.indices[id].map(|i| self.positions[i as usize])
+    }
+
+    pub fn triangle(&self, id: usize) -> TriangleGeometry {
+        let ids = self.indices[id].map(|i| i as usize);
+        let texture_coords = if self.texture_coords.is_empty() {
+            [Vec2f::ZERO; 3]
+        } else {
+            ids.map(|i| self.texture_coords[i])
+        };
+        if self.normals.is_empty() {
+            let mut triangle =
+                TriangleGeometry::new(self.triangle_positions(id), [Dir3::ZERO; 3], texture_coords);
+            triangle.normals = [triangle.geometric_normal(); 3];
+            triangle
+        } else {
+            TriangleGeometry::new(
+                self.triangle_positions(id),
+                ids.map(|i| self.normals[i]),
+                texture_coords,
+            )
+        }
+    }
+
+    pub fn triangle_bounding_box(&self, id: usize) -> Aabb {
+        Aabb::new_surrounding_points(&self.triangle_positions(id))
+    }
+}
+
+// A mesh with a material per triangle and its own hierarchy over the triangles.
+pub struct Mesh<'a> {
+    geometry: &'a MeshGeometry,
+    materials: &'a [&'a Material<'a>],
+    // Index into materials per triangle, may be empty if there is only one material
+    material_ids: &'a [u32],
+    // The unit geometric normal per triangle
+    face_normals: Vec<Dir3>,
+    tree: BvhTree,
+}
+
+impl<'a> Mesh<'a> {
+    pub fn new(
+        geometry: &'a MeshGeometry,
+        materials: &'a [&'a Material<'a>],
+        material_ids: &'a [u32],
+        strategy: BvhStrategy,
+    ) -> Self {
+        let items = (0..geometry.triangle_count())
+            .map(|id| (id, geometry.triangle_bounding_box(id)))
+            .collect::<Vec<_>>();
+        let face_normals = (0..geometry.triangle_count())
+            .map(|id| {
+                let [p0, p1, p2] = geometry.triangle_positions(id);
+                Dir3::cross(p1 - p0, p2 - p0).unit_or_else(Dir3::UP)
+            })
+            .collect();
+        Self {
+            geometry,
+            materials,
+            material_ids,
+            face_normals,
+            tree: BvhTree::new(&items, strategy),
+        }
+    }
+
+    pub fn material(&self, triangle_id: usize) -> &'a Material<'a> {
+        let material_id = self.material_ids.get(triangle_id).copied().unwrap_or(0);
+        self.materials[material_id as usize]
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t_range: &Range<f32>,
+        rng: &mut common::TRng,
+    ) -> Option<HitInteraction<'a>> {
+        let mut t_range = t_range.clone();
+        self.tree.hit(ray, &mut t_range, |id, t_range| {
+            let positions = self.geometry.triangle_positions(id);
+            let (t, weights) = intersect(&positions, ray, t_range)?;
+            let hi = self.hit_interaction(id, &positions, ray, t, weights);
+            if hi.material.skips_hit(&hi, rng) {
+                return None;
+            }
+            t_range.end = t;
+            Some(hi)
+        })
+    }
+
+    // Only hits pay for the vertex attributes
+    fn hit_interaction(
+        &self,
+        id: usize,
+        positions: &[Point3; 3],
+        ray: &Ray,
+        t: f32,
+        weights: [f32; 3],
+    ) -> HitInteraction<'a> {
+        let geometry = self.geometry;
+        let ids = geometry.indices[id].map(|i| i as usize);
+        let [w0, w1, w2] = weights;
+        let face_normal = self.face_normals[id];
+        let surface_normal = if geometry.normals.is_empty() {
+            face_normal
+        } else {
+            math::interpolate(w0, w1, w2, &ids.map(|i| geometry.normals[i]))
+        };
+        let texture_coords = if geometry.texture_coords.is_empty() {
+            [Vec2f::ZERO; 3]
+        } else {
+            ids.map(|i| geometry.texture_coords[i])
+        };
+        let uv = math::interpolate(w0, w1, w2, &texture_coords);
+        let derivatives = position_derivatives(positions, &texture_coords, face_normal);
+        let geo =
+            GeoHitInteraction::new_from_ray(ray, &ray.at(t), &surface_normal, t, uv, derivatives);
+        let mut hi = geo.to_hit_interaction(self.material(id));
+        if !geometry.colors.is_empty() {
+            hi.color = math::interpolate(w0, w1, w2, &ids.map(|i| geometry.colors[i]));
+        }
+        hi
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.tree.bounding_box()
+    }
+}
+
+// The triangles of a mesh as a single point of interest. A triangle is chosen by its weight with
+// a binary search over the cumulative weights, then a point on it uniformly by area.
+pub struct MeshSamplingGeometry {
+    // In world space, only the triangles with a positive weight
+    positions: Vec<[Point3; 3]>,
+    // The probability of choosing any of the triangles up to and including this one
+    cumulative_probabilities: Vec<f32>,
+    area: f32,
+    tree: BvhTree,
+}
+
+impl MeshSamplingGeometry {
+    // None if no triangle has a positive weight.
+    pub fn new(
+        geometry: &MeshGeometry,
+        transformation: &Transformation,
+        weights: &[f32],
+        strategy: BvhStrategy,
+    ) -> Option<Self> {
+        let mut positions = Vec::new();
+        let mut cumulative_probabilities = Vec::new();
+        let mut total_weight = 0.0;
+        for (id, &weight) in weights.iter().enumerate() {
+            if weight > 0.0 {
+                positions.push(
+                    geometry
+                        .triangle_positions(id)
+                        .map(|p| transformation.apply_point(p)),
+                );
+                total_weight += weight;
+                cumulative_probabilities.push(total_weight);
+            }
+        }
+        if positions.is_empty() {
+            return None;
+        }
+        for probability in &mut cumulative_probabilities {
+            *probability /= total_weight;
+        }
+        let area = positions.iter().map(|&p| triangle_area(p)).sum();
+        let items = positions
+            .iter()
+            .enumerate()
+            .map(|(id, p)| (id, Aabb::new_surrounding_points(p)))
+            .collect::<Vec<_>>();
+        Some(Self {
+            positions,
+            cumulative_probabilities,
+            area,
+            tree: BvhTree::new(&items, strategy),
+        })
+    }
+
+    pub fn area(&self) -> f32 {
+        self.area
+    }
+
+    fn probability(&self, id: usize) -> f32 {
+        let previous = if id == 0 {
+            0.0
+        } else {
+            self.cumulative_probabilities[id - 1]
+        };
+        self.cumulative_probabilities[id] - previous
+    }
+
+    pub fn generate(&self, origin: Point3, rng: &mut common::TRng) -> Dir3 {
+        let choice = rng.gen::<f32>();
+        let id = self
+            .cumulative_probabilities
+            .partition_point(|&p| p <= choice)
+            .min(self.positions.len() - 1);
+        (uniform_point(&self.positions[id], rng) - origin).unit()
+    }
+
+    // Sums up over the triangles along the ray, the tree only visits those the ray gets close to.
+    pub fn value(&self, origin: Point3, direction: Dir3) -> f32 {
+        let ray = Ray {
+            origin,
+            direction,
+            time: 0.0,
+        };
+        let mut value = 0.0;
+        self.tree
+            .hit(&ray, &mut (0.001..f32::INFINITY), |id, t_range| {
+                let positions = &self.positions[id];
+                let (t, _) = intersect(positions, &ray, t_range)?;
+                let [p0, p1, p2] = *positions;
+                // Distance squared over the projected area, which is half the area normal
+                let cosine_area = 0.5 * Dir3::dot(Dir3::cross(p1 - p0, p2 - p0), direction).abs();
+                value += self.probability(id) * t * t / cosine_area;
+                None::<()>
+            });
+        value
+    }
+}
+
+fn triangle_area([p0, p1, p2]: [Point3; 3]) -> f32 {
+    0.5 * Dir3::cross(p1 - p0, p2 - p0).length()
+}
@@ -42,16 +42,20 @@ impl RectGeometry {
             {
                 let uv = Vec2f::new(
                     (position.0.e[p0] - self.r0.0) / (self.r0.1 - self.r0.0),
-                    (position.0.e[p1] - self.r1.0) / (self.r0.1 - self.r1.0),
+                    (position.0.e[p1] - self.r1.0) / (self.r1.1 - self.r1.0),
                 );
                 let mut surface_normal = Dir3::ZERO;
                 surface_normal.0.e[n] = -1.0;
+                let (mut dpdu, mut dpdv) = (Dir3::ZERO, Dir3::ZERO);
+                dpdu.0.e[p0] = self.r0.1 - self.r0.0;
+                dpdv.0.e[p1] = self.r1.1 - self.r1.0;
                 return Some(GeoHitInteraction::new_from_ray(
                     ray,
                     &position,
                     &surface_normal,
                     t,
                     uv,
+                    (dpdu, dpdv),
                 ));
             }
         }
@@ -65,6 +69,9 @@ impl RectGeometry {
         e[n] = self.dist;
         (Point3(crate::Vec3 { e }) - origin).unit()
     }
+    pub fn area(&self) -> f32 {
+        (self.r0.1 - self.r0.0) * (self.r1.1 - self.r1.0)
+    }
     pub fn value(&self, origin: Point3, direction: Dir3) -> f32 {
         if let Some(hi) = self.hit(
             &Ray {
@@ -74,7 +81,7 @@ impl RectGeometry {
             },
             &(0.001..f32::INFINITY),
         ) {
-            let area = (self.r0.1 - self.r0.0) * (self.r1.1 - self.r1.0);
+            let area = self.area();
             let distance_squared = hi.t * hi.t;
             let cosine = Dir3::dot(hi.normal, direction).abs();
 
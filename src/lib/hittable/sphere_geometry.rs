@@ -1,6 +1,12 @@
-use std::ops::Range;
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+};
 
-use crate::{Aabb, Dir3, GeoHitInteraction, Point3, Ray, Vec2f};
+use rand::Rng;
+use rand_distr::{Distribution, UnitSphere};
+
+use crate::{common, Aabb, Dir3, GeoHitInteraction, Point3, Ray, Vec2f};
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub struct SphereGeometry {
@@ -49,10 +55,63 @@ impl SphereGeometry {
                 &surface_normal,
                 t,
                 uv,
+                self.position_derivatives(position - self.center),
             ))
         }
     }
 
+    pub fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    // Uniformly samples the cone of directions in which the sphere is seen from origin.
+    pub fn generate(&self, origin: Point3, rng: &mut common::TRng) -> Dir3 {
+        let Some(cos_theta_max) = self.cos_theta_max(origin) else {
+            return Dir3::new_from_arr(UnitSphere.sample(rng));
+        };
+        let w = (self.center - origin).unit();
+        let (u, v) = w.orthonormal_basis();
+        let cos_theta = 1.0 + rng.gen::<f32>() * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (TAU * rng.gen::<f32>()).sin_cos();
+        (sin_theta * cos_phi) * u + (sin_theta * sin_phi) * v + cos_theta * w
+    }
+    pub fn value(&self, origin: Point3, direction: Dir3) -> f32 {
+        let Some(cos_theta_max) = self.cos_theta_max(origin) else {
+            return 1.0 / (4.0 * PI);
+        };
+        let w = (self.center - origin).unit();
+        if Dir3::dot(w, direction) >= cos_theta_max {
+            1.0 / (TAU * (1.0 - cos_theta_max))
+        } else {
+            0.0
+        }
+    }
+    // The cosine of the half opening angle of the cone, None if origin is inside the sphere.
+    fn cos_theta_max(&self, origin: Point3) -> Option<f32> {
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            None
+        } else {
+            Some((1.0 - radius_squared / distance_squared).sqrt())
+        }
+    }
+
+    // dpdu and dpdv for get_sphere_uv at the offset from the center, u is the angle around the
+    // y axis and v the one from the top.
+    fn position_derivatives(&self, offset: Dir3) -> (Dir3, Dir3) {
+        let [x, y, z] = offset.0.e;
+        let radial = (x * x + z * z).sqrt();
+        if radial < 1e-6 * self.radius {
+            // At the poles
+            return offset.unit().orthonormal_basis();
+        }
+        let dpdu = TAU * Dir3::new(z, 0.0, -x);
+        let dpdv = PI * Dir3::new(y * x / radial, -radial, y * z / radial);
+        (dpdu, dpdv)
+    }
+
     fn get_sphere_uv(pos: Dir3) -> Vec2f {
         let (theta, phi, _) = pos.to_radian();
         Vec2f::new(theta, phi)
@@ -1,50 +1,159 @@
 use std::ops::Range;
 
-use crate::{math, Aabb, Dir3, GeoHitInteraction, Point3, Ray, Vec2f};
+use rand::Rng;
+
+use crate::{common, math, Aabb, Dir3, GeoHitInteraction, Point3, Ray, Vec2f};
 
 #[derive(Debug, Clone, Copy)]
 pub struct TriangleGeometry {
     pub positions: [Point3; 3],
     pub normals: [Dir3; 3],
     pub texture_coords: [Vec2f; 3],
+    // Cross product of the edges, its length is twice the area
+    area_normal: Dir3,
+    // The unit area normal, with dpdu and dpdv for all hits
+    geometric_normal: Dir3,
+    derivatives: (Dir3, Dir3),
 }
 
 impl TriangleGeometry {
+    pub fn new(positions: [Point3; 3], normals: [Dir3; 3], texture_coords: [Vec2f; 3]) -> Self {
+        let [p0, p1, p2] = positions;
+        let area_normal = Dir3::cross(p1 - p0, p2 - p0);
+        let geometric_normal = area_normal.unit_or_else(Dir3::UP);
+        Self {
+            positions,
+            normals,
+            texture_coords,
+            area_normal,
+            geometric_normal,
+            derivatives: position_derivatives(&positions, &texture_coords, geometric_normal),
+        }
+    }
+
+    pub fn geometric_normal(&self) -> Dir3 {
+        self.geometric_normal
+    }
+
     pub fn hit(&self, ray: &Ray, t_range: &Range<f32>) -> Option<GeoHitInteraction> {
-        let [p0, p1, p2] = self.positions;
-        let dir1 = p1 - p0;
-        let dir2 = p2 - p0;
-        let normal = Dir3::cross(dir1, dir2).unit();
-        let denom = Dir3::dot(ray.direction, normal);
-        if denom.abs() > 0.0001 {
-            let t = Dir3::dot(p0 - ray.origin, normal) / denom;
-            if t_range.contains(&t) {
-                let position = ray.at(t);
-                let q = position - p0;
-                let v_temp = Dir3::cross(normal, dir2);
-                let w1 = Dir3::dot(q, v_temp) / Dir3::dot(dir1, v_temp);
-                if w1 > 0.0 && w1 < 1.0 {
-                    let v_temp = Dir3::cross(normal, dir1);
-                    let w2 = Dir3::dot(q, v_temp) / Dir3::dot(dir2, v_temp);
-                    let w0 = 1.0 - w1 - w2;
-                    if w2 > 0.0 && w0 > 0.0 {
-                        let uv = math::interpolate(w0, w1, w2, &self.texture_coords);
-                        let surface_normal = math::interpolate(w0, w1, w2, &self.normals);
-                        return Some(GeoHitInteraction::new_from_ray(
-                            ray,
-                            &position,
-                            &surface_normal,
-                            t,
-                            uv,
-                        ));
-                    }
-                }
-            };
-        };
-        None
+        let (t, weights) = intersect(&self.positions, ray, t_range)?;
+        Some(self.hit_interaction(ray, t, weights))
+    }
+
+    // The interaction at the barycentric weights of a hit found by intersect.
+    pub fn hit_interaction(&self, ray: &Ray, t: f32, weights: [f32; 3]) -> GeoHitInteraction {
+        let [w0, w1, w2] = weights;
+        let uv = math::interpolate(w0, w1, w2, &self.texture_coords);
+        let surface_normal = math::interpolate(w0, w1, w2, &self.normals);
+        GeoHitInteraction::new_from_ray(ray, &ray.at(t), &surface_normal, t, uv, self.derivatives)
+    }
+
+    pub fn area(&self) -> f32 {
+        0.5 * self.area_normal.length()
+    }
+
+    pub fn generate(&self, origin: Point3, rng: &mut common::TRng) -> Dir3 {
+        (uniform_point(&self.positions, rng) - origin).unit()
+    }
+    pub fn value(&self, origin: Point3, direction: Dir3) -> f32 {
+        if let Some(hi) = self.hit(
+            &Ray {
+                origin,
+                direction,
+                time: 0.0,
+            },
+            &(0.001..f32::INFINITY),
+        ) {
+            let double_area = self.area_normal.length();
+            let cosine = Dir3::dot(self.area_normal, direction).abs() / double_area;
+            hi.t * hi.t / (cosine * 0.5 * double_area)
+        } else {
+            0.0
+        }
     }
 
     pub fn bounding_box(&self) -> Aabb {
         Aabb::new_surrounding_points(&self.positions)
     }
 }
+
+// A point distributed uniformly over the area of the triangle.
+pub fn uniform_point(positions: &[Point3; 3], rng: &mut common::TRng) -> Point3 {
+    let [p0, p1, p2] = *positions;
+    let s = rng.gen::<f32>().sqrt();
+    let t = rng.gen::<f32>();
+    p0 + (s * (1.0 - t)) * (p1 - p0) + (s * t) * (p2 - p0)
+}
+
+// dpdu and dpdv, constant over a triangle. Without distinct texture coordinates any basis of the
+// plane does.
+pub fn position_derivatives(
+    positions: &[Point3; 3],
+    texture_coords: &[Vec2f; 3],
+    geometric_normal: Dir3,
+) -> (Dir3, Dir3) {
+    let [p0, p1, p2] = *positions;
+    let [uv0, uv1, uv2] = *texture_coords;
+    let (du02, dv02) = (uv0.x - uv2.x, uv0.y - uv2.y);
+    let (du12, dv12) = (uv1.x - uv2.x, uv1.y - uv2.y);
+    let determinant = du02 * dv12 - dv02 * du12;
+    if determinant.abs() < 1e-12 {
+        return geometric_normal.orthonormal_basis();
+    }
+    let (dp02, dp12) = (p0 - p2, p1 - p2);
+    let dpdu = (dv12 * dp02 - dv02 * dp12) / determinant;
+    let dpdv = (du02 * dp12 - du12 * dp02) / determinant;
+    (dpdu, dpdv)
+}
+
+// Watertight intersection by Woop, Benthin and Wald. The triangle is moved into a space where the
+// ray starts at the origin and goes along +z, so the edge tests only depend on the two vertices of
+// the edge and rays through shared edges or vertices hit at least one of the triangles. Returns t
+// and the barycentric weights of the three vertices.
+pub fn intersect(
+    positions: &[Point3; 3],
+    ray: &Ray,
+    t_range: &Range<f32>,
+) -> Option<(f32, [f32; 3])> {
+    let direction = ray.direction.0.e;
+    let kz = (0..3)
+        .max_by(|&a, &b| direction[a].abs().total_cmp(&direction[b].abs()))
+        .unwrap();
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    // Keeps the winding order
+    if direction[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let shear_x = direction[kx] / direction[kz];
+    let shear_y = direction[ky] / direction[kz];
+    let shear_z = 1.0 / direction[kz];
+
+    let [a, b, c] = positions.map(|p| (p - ray.origin).0.e);
+    let sheared = |v: [f32; 3]| (v[kx] - shear_x * v[kz], v[ky] - shear_y * v[kz]);
+    let ((ax, ay), (bx, by), (cx, cy)) = (sheared(a), sheared(b), sheared(c));
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+    // Exactly zero may be rounding, double precision decides the edge
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        let (ax, ay, bx, by, cx, cy) = (
+            ax as f64, ay as f64, bx as f64, by as f64, cx as f64, cy as f64,
+        );
+        u = (cx * by - cy * bx) as f32;
+        v = (ax * cy - ay * cx) as f32;
+        w = (bx * ay - by * ax) as f32;
+    }
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+    let t = (u * a[kz] + v * b[kz] + w * c[kz]) * shear_z / det;
+    if !t_range.contains(&t) {
+        return None;
+    }
+    Some((t, [u / det, v / det, w / det]))
+}
@@ -1,16 +1,20 @@
 pub mod aabb;
 pub mod background_color;
+pub mod bvh;
 pub mod camera;
 pub mod color;
 pub mod common;
 pub mod hittable;
 pub mod material;
 pub mod math;
+pub mod microfacet;
 pub mod perlin;
+pub mod principled;
 pub mod ray;
 pub mod rendering;
 pub mod size2i;
 pub mod texture;
+pub mod tone_mapping;
 pub mod transformations;
 pub mod vec2;
 pub mod vec3;
@@ -18,17 +22,21 @@ pub mod world_scattering_distribution;
 
 pub use self::aabb::*;
 pub use self::background_color::*;
+pub use self::bvh::*;
 pub use self::camera::*;
 pub use self::color::*;
 pub use self::common::*;
 pub use self::hittable::*;
 pub use self::material::*;
 pub use self::math::*;
+pub use self::microfacet::*;
 pub use self::perlin::*;
+pub use self::principled::*;
 pub use self::ray::*;
 pub use self::rendering::*;
 pub use self::size2i::*;
 pub use self::texture::*;
+pub use self::tone_mapping::*;
 pub use self::transformations::*;
 pub use self::vec2::*;
 pub use self::vec3::*;
@@ -2,9 +2,12 @@ use std::f32::consts::PI;
 
 use crate::common;
 use crate::hittable::HitInteraction;
+use crate::microfacet::{self, Ggx, LocalFrame};
+use crate::principled::{Principled, PrincipledLobes};
 use crate::{color::Color, texture::Texture};
 
 use crate::ray::Ray;
+use crate::vec2::Vec2f;
 use crate::vec3::Dir3;
 use rand::Rng;
 use rand_distr::{Distribution, UnitBall, UnitSphere};
@@ -12,6 +15,32 @@ use rand_distr::{Distribution, UnitBall, UnitSphere};
 pub enum MaterialScatteringDistribution {
     Cosine(Dir3),
     Mirror(Dir3),
+    // Reflection at the normals visible from the outgoing direction, which is in the local frame
+    GgxReflection {
+        frame: LocalFrame,
+        outgoing: Dir3,
+        ggx: Ggx,
+    },
+    // Reflection or refraction at the normals visible from the outgoing direction, which is in
+    // the local frame. eta is the index of refraction below the surface divided by the one above.
+    GgxDielectric {
+        frame: LocalFrame,
+        outgoing: Dir3,
+        ggx: Ggx,
+        eta: f32,
+    },
+    // Picks first with the given probability, otherwise second
+    Mixture {
+        first: Box<MaterialScatteringDistribution>,
+        second: Box<MaterialScatteringDistribution>,
+        first_probability: f32,
+    },
+    // The lobes of a principled material, the outgoing direction is in the local frame
+    Principled {
+        frame: LocalFrame,
+        outgoing: Dir3,
+        lobes: PrincipledLobes,
+    },
 }
 
 impl MaterialScatteringDistribution {
@@ -21,6 +50,36 @@ impl MaterialScatteringDistribution {
                 (normal + Dir3::new_from_arr(UnitSphere.sample(rng))).unit_or_else(normal)
             }
             MaterialScatteringDistribution::Mirror(direction) => direction,
+            MaterialScatteringDistribution::GgxReflection {
+                frame,
+                outgoing,
+                ggx,
+            } => {
+                let h = ggx.sample_visible_normal(outgoing, rng);
+                frame.to_world(Dir3::reflect(-outgoing, h))
+            }
+            MaterialScatteringDistribution::GgxDielectric {
+                frame,
+                outgoing,
+                ggx,
+                eta,
+            } => frame.to_world(ggx.sample_dielectric(outgoing, eta, rng)),
+            MaterialScatteringDistribution::Mixture {
+                ref first,
+                ref second,
+                first_probability,
+            } => {
+                if rng.gen::<f32>() < first_probability {
+                    first.generate(rng)
+                } else {
+                    second.generate(rng)
+                }
+            }
+            MaterialScatteringDistribution::Principled {
+                frame,
+                outgoing,
+                lobes,
+            } => frame.to_world(lobes.generate(outgoing, rng)),
         }
     }
     pub fn value(&self, direction: Dir3) -> f32 {
@@ -29,23 +88,163 @@ impl MaterialScatteringDistribution {
                 Dir3::dot(normal, direction).max(0.0) / PI
             }
             MaterialScatteringDistribution::Mirror(_) => f32::INFINITY,
+            MaterialScatteringDistribution::GgxReflection {
+                frame,
+                outgoing,
+                ggx,
+            } => ggx.reflection_pdf(outgoing, frame.to_local(direction)),
+            MaterialScatteringDistribution::GgxDielectric {
+                frame,
+                outgoing,
+                ggx,
+                eta,
+            } => ggx.dielectric_pdf(outgoing, frame.to_local(direction), eta),
+            MaterialScatteringDistribution::Mixture {
+                ref first,
+                ref second,
+                first_probability,
+            } => {
+                first_probability * first.value(direction)
+                    + (1.0 - first_probability) * second.value(direction)
+            }
+            MaterialScatteringDistribution::Principled {
+                frame,
+                outgoing,
+                lobes,
+            } => lobes.pdf(outgoing, frame.to_local(direction)),
         }
     }
     pub fn is_discrete(&self) -> bool {
         match *self {
             MaterialScatteringDistribution::Cosine(_) => false,
             MaterialScatteringDistribution::Mirror(_) => true,
+            MaterialScatteringDistribution::GgxReflection { .. }
+            | MaterialScatteringDistribution::GgxDielectric { .. } => false,
+            // Only continuous distributions are mixed
+            MaterialScatteringDistribution::Mixture { .. } => false,
+            MaterialScatteringDistribution::Principled { .. } => false,
         }
     }
 }
 
+// Beer-Lambert absorption coefficients per unit length for which white light turns into color
+// after distance. Without a positive distance nothing is absorbed.
+pub fn absorption_from_color_at_distance(color: Color, distance: f32) -> Color {
+    if distance.is_nan() || distance <= 0.0 {
+        return Color::BLACK;
+    }
+    Color::new_rgb_arr(color.0.e.map(|c| -c.max(1e-6).ln() / distance))
+}
+
+// The fraction of light left after travelling through the interior to a hit from inside.
+fn interior_transmittance(absorption: Color, ray: &Ray, interaction: &HitInteraction) -> Color {
+    if interaction.front_face || absorption == Color::BLACK {
+        return Color::WHITE;
+    }
+    let distance = interaction.t * ray.direction.length();
+    Color::new_rgb_arr(absorption.0.e.map(|a| (-a * distance).exp()))
+}
+
+// Measured complex indices of refraction of common metals
+#[derive(Debug, Clone, Copy)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    // eta and k at red, green and blue wavelengths
+    pub fn complex_ior(self) -> (Color, Color) {
+        let (eta, k) = match self {
+            ConductorPreset::Gold => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+            ConductorPreset::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            ConductorPreset::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            ConductorPreset::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        };
+        (Color::new_rgb_arr(eta), Color::new_rgb_arr(k))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Material<'a> {
-    Lambert { albedo: &'a Texture<'a> },
-    Metal { albedo: &'a Texture<'a>, fuzz: f32 },
-    Dielectric { index_of_refraction: f32 },
-    DiffuseLight { emit: &'a Texture<'a> },
-    Isotropic { albedo: &'a Texture<'a> },
+    Lambert {
+        albedo: &'a Texture<'a>,
+    },
+    // Rough diffuse surfaces like clay or the moon, sigma is the standard deviation of the facet
+    // slopes in radians, read from the red channel
+    OrenNayar {
+        albedo: &'a Texture<'a>,
+        sigma: &'a Texture<'a>,
+    },
+    Metal {
+        albedo: &'a Texture<'a>,
+        fuzz: f32,
+    },
+    // GGX microfacets with the complex index of refraction eta + i k, albedo tints the Fresnel
+    // reflectance
+    Conductor {
+        albedo: &'a Texture<'a>,
+        eta: Color,
+        k: Color,
+        ggx: Ggx,
+    },
+    // absorption is the Beer-Lambert coefficient per unit length inside the dielectric
+    Dielectric {
+        index_of_refraction: f32,
+        absorption: Color,
+    },
+    // GGX microfacets on the boundary of a dielectric, transmitted light is tinted
+    RoughDielectric {
+        index_of_refraction: f32,
+        tint: &'a Texture<'a>,
+        ggx: Ggx,
+        absorption: Color,
+    },
+    // A clear dielectric coat with GGX microfacets over the base material, which must not be
+    // coated itself, see is_coated. Light reaching the base is reduced by the Fresnel
+    // transmittance of the coat on the way in and out.
+    Coated {
+        base: &'a Material<'a>,
+        index_of_refraction: f32,
+        ggx: Ggx,
+    },
+    // A Disney style uber material with textured parameters
+    Principled(Principled<'a>),
+    // The base with its shading normal taken from a tangent space normal map, whose green
+    // channel points up in the image as in glTF. strength scales the tangential part.
+    NormalMapped {
+        base: &'a Material<'a>,
+        map: &'a Texture<'a>,
+        strength: f32,
+    },
+    // The base with its shading normal tilted by the slopes of the red channel of height, which
+    // is scaled by scale
+    BumpMapped {
+        base: &'a Material<'a>,
+        height: &'a Texture<'a>,
+        scale: f32,
+    },
+    // The base where the red channel of opacity reaches cutoff, elsewhere hits are skipped.
+    // Without a cutoff hits are kept with the probability of the opacity.
+    Cutout {
+        base: &'a Material<'a>,
+        opacity: &'a Texture<'a>,
+        cutoff: Option<f32>,
+    },
+    // Blends first into second by the red channel of factor, like rust patches on a metal
+    Mix {
+        first: &'a Material<'a>,
+        second: &'a Material<'a>,
+        factor: &'a Texture<'a>,
+    },
+    DiffuseLight {
+        emit: &'a Texture<'a>,
+    },
+    Isotropic {
+        albedo: &'a Texture<'a>,
+    },
 }
 
 impl<'a> Material<'a> {
@@ -56,7 +255,7 @@ impl<'a> Material<'a> {
         rng: &mut common::TRng,
     ) -> Option<(Color, MaterialScatteringDistribution)> {
         match *self {
-            Material::Lambert { albedo } => {
+            Material::Lambert { albedo } | Material::OrenNayar { albedo, .. } => {
                 let color = albedo.sample(interaction);
                 let pdf = MaterialScatteringDistribution::Cosine(interaction.normal);
                 Some((color, pdf))
@@ -77,8 +276,35 @@ impl<'a> Material<'a> {
                     None
                 }
             }
+            Material::Conductor {
+                albedo,
+                eta,
+                k,
+                ggx,
+            } => {
+                let frame = LocalFrame::new(interaction.normal);
+                let outgoing = frame.to_local(-ray.direction.unit());
+                if outgoing.0.e[2] <= 0.0 {
+                    return None;
+                }
+                let albedo = albedo.sample(interaction);
+                if ggx.is_smooth() {
+                    let fresnel = microfacet::fresnel_conductor(outgoing.0.e[2], eta, k);
+                    let direction = Dir3::reflect(ray.direction.unit(), interaction.normal);
+                    let scattered = MaterialScatteringDistribution::Mirror(direction);
+                    Some((Color::convolution(albedo, fresnel), scattered))
+                } else {
+                    let scattered = MaterialScatteringDistribution::GgxReflection {
+                        frame,
+                        outgoing,
+                        ggx,
+                    };
+                    Some((albedo, scattered))
+                }
+            }
             Material::Dielectric {
                 index_of_refraction,
+                absorption,
             } => {
                 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
                     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
@@ -101,8 +327,177 @@ impl<'a> Material<'a> {
                     Dir3::refract(ray.direction, interaction.normal, refraction_ratio)
                 };
                 let scattered = MaterialScatteringDistribution::Mirror(direction.unit());
+                Some((
+                    interior_transmittance(absorption, ray, interaction),
+                    scattered,
+                ))
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                tint,
+                ggx,
+                absorption,
+            } => {
+                let eta = if interaction.front_face {
+                    index_of_refraction
+                } else {
+                    1.0 / index_of_refraction
+                };
+                let frame = LocalFrame::new(interaction.normal);
+                let outgoing = frame.to_local(-ray.direction.unit());
+                if outgoing.0.e[2] <= 0.0 {
+                    return None;
+                }
+                let transmittance = interior_transmittance(absorption, ray, interaction);
+                if !ggx.is_smooth() {
+                    let scattered = MaterialScatteringDistribution::GgxDielectric {
+                        frame,
+                        outgoing,
+                        ggx,
+                        eta,
+                    };
+                    // The tint is part of scattering_pdf, as only transmission is tinted
+                    return Some((transmittance, scattered));
+                }
+                let fresnel = microfacet::fresnel_dielectric(outgoing.0.e[2], eta);
+                let direction = if rng.gen::<f32>() < fresnel {
+                    None
+                } else {
+                    microfacet::refract(-ray.direction.unit(), interaction.normal, eta)
+                };
+                match direction {
+                    Some(direction) => Some((
+                        Color::convolution(transmittance, tint.sample(interaction)),
+                        MaterialScatteringDistribution::Mirror(direction),
+                    )),
+                    None => Some((
+                        transmittance,
+                        MaterialScatteringDistribution::Mirror(Dir3::reflect(
+                            ray.direction.unit(),
+                            interaction.normal,
+                        )),
+                    )),
+                }
+            }
+            Material::Coated {
+                base,
+                index_of_refraction,
+                ggx,
+            } => {
+                let frame = LocalFrame::new(interaction.normal);
+                let outgoing = frame.to_local(-ray.direction.unit());
+                if outgoing.0.e[2] <= 0.0 {
+                    return None;
+                }
+                let fresnel = microfacet::fresnel_dielectric(outgoing.0.e[2], index_of_refraction);
+                let coat = if ggx.is_smooth() {
+                    let direction = Dir3::reflect(ray.direction.unit(), interaction.normal);
+                    MaterialScatteringDistribution::Mirror(direction)
+                } else {
+                    MaterialScatteringDistribution::GgxReflection {
+                        frame,
+                        outgoing,
+                        ggx,
+                    }
+                };
+                // scattering_pdf covers the coat and the continuous part of the base together,
+                // every continuous distribution is weighted by the chance to return one.
+                let continuous = self.continuous_probability(ray, interaction);
+                // Even a weak coat gets enough samples for its highlights
+                let coat_probability = fresnel.clamp(0.25, 0.75);
+                let base_scattered = match base.scatter(ray, interaction, rng) {
+                    Some((_, base_distribution))
+                        if !coat.is_discrete() && !base_distribution.is_discrete() =>
+                    {
+                        let scattered = MaterialScatteringDistribution::Mixture {
+                            first: Box::new(coat),
+                            second: Box::new(base_distribution),
+                            first_probability: coat_probability,
+                        };
+                        return Some((Color::WHITE / continuous, scattered));
+                    }
+                    base_scattered => base_scattered,
+                };
+                // A discrete distribution can't be mixed, one of them is picked and weighted
+                // by the inverse of its probability.
+                if rng.gen::<f32>() < coat_probability {
+                    if coat.is_discrete() {
+                        Some((Color::WHITE * (fresnel / coat_probability), coat))
+                    } else {
+                        Some((Color::WHITE / continuous, coat))
+                    }
+                } else {
+                    let (base_attenuation, base_distribution) = base_scattered?;
+                    if base_distribution.is_discrete() {
+                        let direction = base_distribution.generate(rng);
+                        let cos_i = Dir3::dot(direction, interaction.normal).abs();
+                        let transmitted = (1.0 - fresnel)
+                            * (1.0 - microfacet::fresnel_dielectric(cos_i, index_of_refraction));
+                        let attenuation =
+                            base_attenuation * (transmitted / (1.0 - coat_probability));
+                        Some((attenuation, base_distribution))
+                    } else {
+                        Some((Color::WHITE / continuous, base_distribution))
+                    }
+                }
+            }
+            Material::Principled(ref principled) => {
+                let frame = LocalFrame::new(interaction.normal);
+                let outgoing = frame.to_local(-ray.direction.unit());
+                if outgoing.0.e[2] <= 0.0 {
+                    return None;
+                }
+                let scattered = MaterialScatteringDistribution::Principled {
+                    frame,
+                    outgoing,
+                    lobes: principled.lobes(interaction),
+                };
+                // The base color is part of scattering_pdf, as transmission is tinted differently
                 Some((Color::WHITE, scattered))
             }
+            Material::NormalMapped { base, .. } | Material::BumpMapped { base, .. } => {
+                base.scatter(ray, &self.shading_interaction(ray, interaction), rng)
+            }
+            Material::Cutout { base, .. } => base.scatter(ray, interaction, rng),
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = Self::mix_weight(first, second, factor, interaction);
+                let pick_second = rng.gen::<f32>() < second_weight;
+                let (picked, other, other_weight) = if pick_second {
+                    (second, first, 1.0 - second_weight)
+                } else {
+                    (first, second, second_weight)
+                };
+                let (attenuation, distribution) = picked.scatter(ray, interaction, rng)?;
+                // The discrete part of the picked component is weighted by the chance to pick it
+                if distribution.is_discrete() {
+                    return Some((attenuation, distribution));
+                }
+                // scattering_pdf covers the continuous part of both components with their
+                // attenuation, which is sampled with this chance
+                let continuous = (1.0 - other_weight)
+                    * picked.continuous_probability(ray, interaction)
+                    + other_weight * other.continuous_probability(ray, interaction);
+                let other_distribution = if other_weight > 0.0 {
+                    other
+                        .scatter(ray, interaction, rng)
+                        .filter(|(_, d)| !d.is_discrete())
+                } else {
+                    None
+                };
+                let scattered = match other_distribution {
+                    Some((_, other_distribution)) => MaterialScatteringDistribution::Mixture {
+                        first: Box::new(distribution),
+                        second: Box::new(other_distribution),
+                        first_probability: 1.0 - other_weight,
+                    },
+                    None => distribution,
+                };
+                Some((Color::WHITE / continuous, scattered))
+            }
             Material::Isotropic { albedo } => {
                 let scattered = MaterialScatteringDistribution::Mirror(Dir3::new_from_arr(
                     UnitSphere.sample(rng),
@@ -113,26 +508,354 @@ impl<'a> Material<'a> {
         }
     }
 
+    // The scattering function times the cosine to the normal per channel, without the
+    // attenuation returned by scatter. Discrete distributions are not covered.
     pub fn scattering_pdf(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         ray_scattered: &Ray,
         interaction: &HitInteraction,
-    ) -> f32 {
+    ) -> Color {
         match *self {
             Material::Lambert { albedo: _ } => {
                 let cosine = Dir3::dot(interaction.normal, ray_scattered.direction);
                 let clamped_cosine = cosine.max(0.0);
-                clamped_cosine / PI
+                Color::WHITE * (clamped_cosine / PI)
             }
-            _ => 0.0,
+            Material::OrenNayar { sigma, .. } => {
+                let normal = interaction.normal;
+                let wo = -ray_in.direction.unit();
+                let wi = ray_scattered.direction.unit();
+                let (cos_o, cos_i) = (Dir3::dot(normal, wo), Dir3::dot(normal, wi));
+                if cos_o <= 0.0 || cos_i <= 0.0 {
+                    return Color::BLACK;
+                }
+                let sigma2 = sigma.sample(interaction).0.e[0].powi(2);
+                let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+                let b = 0.45 * sigma2 / (sigma2 + 0.09);
+                // cos(phi_i - phi_o) sin(alpha) tan(beta), from the projections onto the surface
+                let projected = Dir3::dot(wo - cos_o * normal, wi - cos_i * normal);
+                let retro = projected.max(0.0) / cos_o.max(cos_i);
+                Color::WHITE * ((a + b * retro) * cos_i / PI)
+            }
+            Material::Conductor { eta, k, ggx, .. } if !ggx.is_smooth() => {
+                let frame = LocalFrame::new(interaction.normal);
+                let wo = frame.to_local(-ray_in.direction.unit());
+                let wi = frame.to_local(ray_scattered.direction.unit());
+                let (cos_o, cos_i) = (wo.0.e[2], wi.0.e[2]);
+                if cos_o <= 0.0 || cos_i <= 0.0 {
+                    return Color::BLACK;
+                }
+                let h = (wo + wi).unit();
+                let fresnel = microfacet::fresnel_conductor(Dir3::dot(wo, h), eta, k);
+                fresnel * (ggx.d(h) * ggx.g(wo, wi) / (4.0 * cos_o))
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                tint,
+                ggx,
+                ..
+            } if !ggx.is_smooth() => {
+                let eta = if interaction.front_face {
+                    index_of_refraction
+                } else {
+                    1.0 / index_of_refraction
+                };
+                let frame = LocalFrame::new(interaction.normal);
+                let wo = frame.to_local(-ray_in.direction.unit());
+                let wi = frame.to_local(ray_scattered.direction.unit());
+                let (reflected, transmitted) = ggx.dielectric_value(wo, wi, eta);
+                Color::WHITE * reflected + tint.sample(interaction) * transmitted
+            }
+            Material::Coated {
+                base,
+                index_of_refraction,
+                ggx,
+            } => {
+                let frame = LocalFrame::new(interaction.normal);
+                let wo = frame.to_local(-ray_in.direction.unit());
+                let wi = frame.to_local(ray_scattered.direction.unit());
+                if wo.0.e[2] <= 0.0 {
+                    return Color::BLACK;
+                }
+                let coat = if ggx.is_smooth() {
+                    0.0
+                } else {
+                    ggx.dielectric_value(wo, wi, index_of_refraction).0
+                };
+                let transmitted = (1.0
+                    - microfacet::fresnel_dielectric(wo.0.e[2], index_of_refraction))
+                    * (1.0 - microfacet::fresnel_dielectric(wi.0.e[2].abs(), index_of_refraction));
+                let base = Color::convolution(
+                    base.scattering_attenuation(ray_in, interaction),
+                    base.scattering_pdf(ray_in, ray_scattered, interaction),
+                );
+                Color::WHITE * coat + base * transmitted
+            }
+            Material::Principled(ref principled) => {
+                let frame = LocalFrame::new(interaction.normal);
+                let wo = frame.to_local(-ray_in.direction.unit());
+                let wi = frame.to_local(ray_scattered.direction.unit());
+                principled.value(wo, wi, interaction)
+            }
+            Material::NormalMapped { base, .. } | Material::BumpMapped { base, .. } => {
+                let interaction = self.shading_interaction(ray_in, interaction);
+                base.scattering_pdf(ray_in, ray_scattered, &interaction)
+            }
+            Material::Cutout { base, .. } => {
+                base.scattering_pdf(ray_in, ray_scattered, interaction)
+            }
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = Self::mix_weight(first, second, factor, interaction);
+                let component = |material: &Material, weight: f32| {
+                    if weight <= 0.0 {
+                        return Color::BLACK;
+                    }
+                    Color::convolution(
+                        material.scattering_attenuation(ray_in, interaction),
+                        material.scattering_pdf(ray_in, ray_scattered, interaction),
+                    ) * weight
+                };
+                component(first, 1.0 - second_weight) + component(second, second_weight)
+            }
+            _ => Color::BLACK,
+        }
+    }
+
+    // The attenuation scatter returns along with a continuous distribution, as it doesn't depend
+    // on the scattered direction.
+    pub fn scattering_attenuation(&self, ray: &Ray, interaction: &HitInteraction) -> Color {
+        match *self {
+            Material::Lambert { albedo }
+            | Material::OrenNayar { albedo, .. }
+            | Material::Conductor { albedo, .. } => albedo.sample(interaction),
+            Material::RoughDielectric { absorption, .. } => {
+                interior_transmittance(absorption, ray, interaction)
+            }
+            Material::NormalMapped { base, .. } | Material::BumpMapped { base, .. } => {
+                base.scattering_attenuation(ray, &self.shading_interaction(ray, interaction))
+            }
+            Material::Cutout { base, .. } => base.scattering_attenuation(ray, interaction),
+            _ => Color::WHITE,
+        }
+    }
+
+    // The mean emitted radiance over the surface
+    pub fn average_emission(&self) -> Color {
+        match *self {
+            Material::DiffuseLight { emit } => emit.average(),
+            Material::NormalMapped { base, .. }
+            | Material::BumpMapped { base, .. }
+            | Material::Cutout { base, .. } => base.average_emission(),
+            // Ignores how the factor correlates with the emission of the components
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = factor.average().0.e[0].clamp(0.0, 1.0);
+                first.average_emission() * (1.0 - second_weight)
+                    + second.average_emission() * second_weight
+            }
+            _ => Color::BLACK,
         }
     }
 
     pub fn emit(&self, interaction: &HitInteraction) -> Color {
         match *self {
             Material::DiffuseLight { emit } => emit.sample(interaction),
+            Material::NormalMapped { base, .. }
+            | Material::BumpMapped { base, .. }
+            | Material::Cutout { base, .. } => base.emit(interaction),
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = Self::mix_weight(first, second, factor, interaction);
+                first.emit(interaction) * (1.0 - second_weight)
+                    + second.emit(interaction) * second_weight
+            }
             _ => Color::BLACK,
         }
     }
+
+    // Whether a ray passes through the surface at the hit instead of interacting with it
+    pub fn skips_hit(&self, interaction: &HitInteraction, rng: &mut common::TRng) -> bool {
+        let opacity = self.opacity(interaction);
+        opacity < 1.0 && rng.gen::<f32>() >= opacity
+    }
+
+    // The chance that a ray interacts with the surface at the hit
+    pub fn opacity(&self, interaction: &HitInteraction) -> f32 {
+        match *self {
+            Material::Cutout {
+                opacity, cutoff, ..
+            } => {
+                let opacity = opacity.sample(interaction).0.e[0];
+                match cutoff {
+                    Some(cutoff) if opacity < cutoff => 0.0,
+                    Some(_) => 1.0,
+                    None => opacity.clamp(0.0, 1.0),
+                }
+            }
+            Material::NormalMapped { base, .. } | Material::BumpMapped { base, .. } => {
+                base.opacity(interaction)
+            }
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = factor.sample(interaction).0.e[0].clamp(0.0, 1.0);
+                (1.0 - second_weight) * first.opacity(interaction)
+                    + second_weight * second.opacity(interaction)
+            }
+            _ => 1.0,
+        }
+    }
+
+    // The chance that scatter returns a continuous distribution
+    pub fn continuous_probability(&self, ray: &Ray, interaction: &HitInteraction) -> f32 {
+        match *self {
+            Material::Lambert { .. } | Material::OrenNayar { .. } | Material::Principled(_) => 1.0,
+            Material::Conductor { ggx, .. } | Material::RoughDielectric { ggx, .. } => {
+                if ggx.is_smooth() {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Material::Coated {
+                base,
+                index_of_refraction,
+                ggx,
+            } => {
+                let cos_o = Dir3::dot(-ray.direction.unit(), interaction.normal);
+                if cos_o <= 0.0 {
+                    return 0.0;
+                }
+                let coat_probability =
+                    microfacet::fresnel_dielectric(cos_o, index_of_refraction).clamp(0.25, 0.75);
+                let base_continuous = base.continuous_probability(ray, interaction);
+                if ggx.is_smooth() {
+                    (1.0 - coat_probability) * base_continuous
+                } else {
+                    base_continuous + (1.0 - base_continuous) * coat_probability
+                }
+            }
+            Material::NormalMapped { base, .. } | Material::BumpMapped { base, .. } => {
+                base.continuous_probability(ray, &self.shading_interaction(ray, interaction))
+            }
+            Material::Cutout { base, .. } => base.continuous_probability(ray, interaction),
+            Material::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let second_weight = Self::mix_weight(first, second, factor, interaction);
+                (1.0 - second_weight) * first.continuous_probability(ray, interaction)
+                    + second_weight * second.continuous_probability(ray, interaction)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // Whether the material has a coat, also inside of wrappers and mixes
+    pub fn is_coated(&self) -> bool {
+        match self {
+            Material::Coated { .. } => true,
+            Material::NormalMapped { base, .. }
+            | Material::BumpMapped { base, .. }
+            | Material::Cutout { base, .. } => base.is_coated(),
+            Material::Mix { first, second, .. } => first.is_coated() || second.is_coated(),
+            _ => false,
+        }
+    }
+
+    // The weight of the second component of a mix among the hits which are kept, as the more
+    // opaque component is hit more often
+    fn mix_weight(
+        first: &Material,
+        second: &Material,
+        factor: &Texture,
+        interaction: &HitInteraction,
+    ) -> f32 {
+        let second_weight = factor.sample(interaction).0.e[0].clamp(0.0, 1.0);
+        let first_opaque = (1.0 - second_weight) * first.opacity(interaction);
+        let second_opaque = second_weight * second.opacity(interaction);
+        if first_opaque + second_opaque > 0.0 {
+            second_opaque / (first_opaque + second_opaque)
+        } else {
+            second_weight
+        }
+    }
+
+    // The interaction with the shading normal of a normal or bump mapped material. It is bent
+    // towards the ray where it would face away from it, as the base would turn black.
+    fn shading_interaction(
+        &self,
+        ray: &Ray,
+        interaction: &HitInteraction<'a>,
+    ) -> HitInteraction<'a> {
+        let normal = interaction.normal;
+        let shading_normal = match *self {
+            Material::NormalMapped { map, strength, .. } => {
+                let tangent = (interaction.dpdu - Dir3::dot(interaction.dpdu, normal) * normal)
+                    .unit_or_else(normal.orthonormal_basis().0);
+                // Texture coordinates grow downwards in the image
+                let mut up = Dir3::cross(normal, tangent);
+                if Dir3::dot(up, interaction.dpdv) > 0.0 {
+                    up = -up;
+                }
+                let [x, y, z] = map.sample(interaction).0.e.map(|c| 2.0 * c - 1.0);
+                strength * x * tangent + strength * y * up + z.max(0.0) * normal
+            }
+            Material::BumpMapped { height, scale, .. } => {
+                const DELTA: f32 = 1e-3;
+                let sample = |du: f32, dv: f32| {
+                    let mut shifted = interaction.clone();
+                    let offset = du * interaction.dpdu + dv * interaction.dpdv;
+                    shifted.position = interaction.position + offset;
+                    shifted.uv = Vec2f::new(interaction.uv.x + du, interaction.uv.y + dv);
+                    scale * height.sample(&shifted).0.e[0]
+                };
+                let center = sample(0.0, 0.0);
+                let dhdu = (sample(DELTA, 0.0) - center) / DELTA;
+                let dhdv = (sample(0.0, DELTA) - center) / DELTA;
+                // Displaced along the outward normal, so that both sides agree
+                let outward = if interaction.front_face {
+                    normal
+                } else {
+                    -normal
+                };
+                let dpdu = interaction.dpdu + dhdu * outward;
+                let dpdv = interaction.dpdv + dhdv * outward;
+                let bumped = Dir3::cross(dpdu, dpdv);
+                if Dir3::dot(bumped, normal) < 0.0 {
+                    -bumped
+                } else {
+                    bumped
+                }
+            }
+            _ => normal,
+        };
+        let shading_normal = shading_normal.unit_or_else(normal);
+        let outgoing = -ray.direction.unit();
+        let cos_o = Dir3::dot(shading_normal, outgoing);
+        const MIN_COS: f32 = 0.01;
+        let shading_normal = if cos_o < MIN_COS {
+            (shading_normal + (MIN_COS - cos_o) * outgoing).unit_or_else(normal)
+        } else {
+            shading_normal
+        };
+        HitInteraction {
+            normal: shading_normal,
+            ..interaction.clone()
+        }
+    }
 }
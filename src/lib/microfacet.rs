@@ -0,0 +1,248 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::{common, Color, Dir3};
+
+// An orthonormal basis around a normal. Local directions have the normal as z.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalFrame {
+    pub tangent: Dir3,
+    pub bitangent: Dir3,
+    pub normal: Dir3,
+}
+
+impl LocalFrame {
+    pub fn new(normal: Dir3) -> Self {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    pub fn to_local(&self, v: Dir3) -> Dir3 {
+        Dir3::new(
+            Dir3::dot(v, self.tangent),
+            Dir3::dot(v, self.bitangent),
+            Dir3::dot(v, self.normal),
+        )
+    }
+
+    pub fn to_world(&self, v: Dir3) -> Dir3 {
+        let [x, y, z] = v.0.e;
+        x * self.tangent + y * self.bitangent + z * self.normal
+    }
+}
+
+// The isotropic GGX (Trowbridge-Reitz) distribution of microfacet normals. All directions are
+// in the local frame of the surface and point away from it.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha: f32,
+}
+
+impl Ggx {
+    // Below this alpha the distribution is treated as a perfectly smooth surface.
+    pub const MIN_ALPHA: f32 = 1e-3;
+
+    // Perceptually linear roughness in [0, 1], squared as usual.
+    pub fn new_roughness(roughness: f32) -> Self {
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < Self::MIN_ALPHA
+    }
+
+    // Density of the microfacet normal h per projected area
+    pub fn d(&self, h: Dir3) -> f32 {
+        let [x, y, z] = h.0.e;
+        if z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let e = (x * x + y * y) / a2 + z * z;
+        1.0 / (PI * a2 * e * e)
+    }
+
+    fn lambda(&self, w: Dir3) -> f32 {
+        let [x, y, z] = w.0.e;
+        let tan2 = (x * x + y * y) / (z * z);
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    // Fraction of the microfacets visible from w
+    pub fn g1(&self, w: Dir3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated fraction visible from both directions
+    pub fn g(&self, wo: Dir3, wi: Dir3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals visible from wo, which sample_visible_normal follows.
+    pub fn visible_normal_pdf(&self, wo: Dir3, h: Dir3) -> f32 {
+        let cos_o = wo.0.e[2];
+        if cos_o <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Dir3::dot(wo, h).max(0.0) * self.d(h) / cos_o
+    }
+
+    // Heitz, "Sampling the GGX Distribution of Visible Normals", 2018.
+    pub fn sample_visible_normal(&self, wo: Dir3, rng: &mut common::TRng) -> Dir3 {
+        let [x, y, z] = wo.0.e;
+        // Stretch to the hemisphere configuration
+        let v = Dir3::new(self.alpha * x, self.alpha * y, z).unit();
+        let [vx, vy, vz] = v.0.e;
+        let len_sq = vx * vx + vy * vy;
+        let t1 = if len_sq > 0.0 {
+            Dir3::new(-vy, vx, 0.0) / len_sq.sqrt()
+        } else {
+            Dir3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Dir3::cross(v, t1);
+        // A disk sample, compressed on the side hidden from wo
+        let r = rng.gen::<f32>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vz);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+        let [nx, ny, nz] = n.0.e;
+        Dir3::new(self.alpha * nx, self.alpha * ny, nz.max(0.0)).unit()
+    }
+
+    // Density of the reflection of wo at a visible normal, per solid angle of wi.
+    pub fn reflection_pdf(&self, wo: Dir3, wi: Dir3) -> f32 {
+        let h = (wo + wi).unit_or_else(Dir3::ZERO);
+        let cos_h = Dir3::dot(wo, h);
+        if cos_h <= 0.0 {
+            return 0.0;
+        }
+        self.visible_normal_pdf(wo, h) / (4.0 * cos_h)
+    }
+
+    // Walter et al., "Microfacet Models for Refraction through Rough Surfaces", 2007. wo is
+    // above the surface and eta the index of refraction below divided by the one above.
+
+    // Reflects or refracts wo at a visible normal, chosen by the Fresnel reflectance.
+    pub fn sample_dielectric(&self, wo: Dir3, eta: f32, rng: &mut common::TRng) -> Dir3 {
+        let h = self.sample_visible_normal(wo, rng);
+        let cos_o = Dir3::dot(wo, h);
+        match refract(wo, h, eta) {
+            Some(wi) if rng.gen::<f32>() >= fresnel_dielectric(cos_o, eta) => wi,
+            _ => Dir3::reflect(-wo, h),
+        }
+    }
+
+    // Density of sample_dielectric per solid angle of wi
+    pub fn dielectric_pdf(&self, wo: Dir3, wi: Dir3, eta: f32) -> f32 {
+        let Some((h, reflected)) = dielectric_half_vector(wo, wi, eta) else {
+            return 0.0;
+        };
+        let (cos_o, cos_i) = (Dir3::dot(wo, h), Dir3::dot(wi, h));
+        let fresnel = fresnel_dielectric(cos_o, eta);
+        let visible = self.visible_normal_pdf(wo, h);
+        if reflected {
+            fresnel * visible / (4.0 * cos_o)
+        } else {
+            let denom = cos_o + eta * cos_i;
+            (1.0 - fresnel) * visible * eta * eta * cos_i.abs() / (denom * denom)
+        }
+    }
+
+    // The scattering function times |cos wi|, split into the reflected and transmitted part of
+    // which at most one is not zero.
+    pub fn dielectric_value(&self, wo: Dir3, wi: Dir3, eta: f32) -> (f32, f32) {
+        let Some((h, reflected)) = dielectric_half_vector(wo, wi, eta) else {
+            return (0.0, 0.0);
+        };
+        let (cos_o, cos_i) = (Dir3::dot(wo, h), Dir3::dot(wi, h));
+        let fresnel = fresnel_dielectric(cos_o, eta);
+        let dg = self.d(h) * self.g(wo, wi);
+        if reflected {
+            (fresnel * dg / (4.0 * wo.0.e[2]), 0.0)
+        } else {
+            let denom = cos_o + eta * cos_i;
+            let value = (1.0 - fresnel) * dg * eta * eta * cos_i.abs() * cos_o
+                / (wo.0.e[2] * denom * denom);
+            (0.0, value)
+        }
+    }
+}
+
+// The microfacet normal which turns wo into wi and whether that is a reflection. None if wi can't
+// be reached from wo by a microfacet facing wo.
+fn dielectric_half_vector(wo: Dir3, wi: Dir3, eta: f32) -> Option<(Dir3, bool)> {
+    let (cos_o, cos_i) = (wo.0.e[2], wi.0.e[2]);
+    if cos_o <= 0.0 || cos_i == 0.0 {
+        return None;
+    }
+    let reflected = cos_i > 0.0;
+    let h = if reflected { wo + wi } else { -(wo + eta * wi) };
+    let h = h.unit_or_else(Dir3::ZERO);
+    let h = if h.0.e[2] < 0.0 { -h } else { h };
+    // Both sides of the microfacet have to match the sides of the surface
+    let (cos_oh, cos_ih) = (Dir3::dot(wo, h), Dir3::dot(wi, h));
+    if h.0.e[2] == 0.0 || cos_oh <= 0.0 || (cos_ih > 0.0) != reflected {
+        return None;
+    }
+    Some((h, reflected))
+}
+
+// The direction of wo refracted at the normal n on its side, None for total internal reflection.
+// eta is the index of refraction on the other side divided by the one on the side of wo.
+pub fn refract(wo: Dir3, n: Dir3, eta: f32) -> Option<Dir3> {
+    let cos_o = Dir3::dot(wo, n);
+    let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + (cos_o / eta - cos_t) * n)
+}
+
+// The unpolarized reflectance of a dielectric, cos_theta is on the side of the incoming light and
+// eta the index of refraction on the other side divided by the one on this side.
+pub fn fresnel_dielectric(cos_theta: f32, eta: f32) -> f32 {
+    let sin2_t = (1.0 - cos_theta * cos_theta).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_theta - eta * cos_t) / (cos_theta + eta * cos_t);
+    let rp = (eta * cos_theta - cos_t) / (eta * cos_theta + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// The unpolarized reflectance of a conductor with the complex index of refraction eta + i k per
+// channel, cos_theta is between the direction and the (micro) normal.
+pub fn fresnel_conductor(cos_theta: f32, eta: Color, k: Color) -> Color {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_theta * cos_theta;
+        let sin2 = 1.0 - cos2;
+        let (eta2, k2) = (eta * eta, k * k);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    let [eta, k] = [eta.0.e, k.0.e];
+    Color::new_rgb(
+        channel(eta[0], k[0]),
+        channel(eta[1], k[1]),
+        channel(eta[2], k[2]),
+    )
+}
@@ -46,7 +46,7 @@ impl Perlin {
         accum.abs()
     }
     pub fn noise(&self, p: Point3) -> f32 {
-        let mask = ((1 << self.bits) - 1) as i32;
+        let mask = (1 << self.bits) - 1;
         let u = p.0.e[0] - p.0.e[0].floor();
         let v = p.0.e[1] - p.0.e[1].floor();
         let w = p.0.e[2] - p.0.e[2].floor();
@@ -91,7 +91,7 @@ impl Perlin {
     }
 
     fn generate_per(bits: usize, rng: &mut TRng) -> Vec<u32> {
-        let mut result = (0..(1 << bits) as u32).into_iter().collect::<Vec<_>>();
+        let mut result = (0..(1 << bits) as u32).collect::<Vec<_>>();
         result.shuffle(rng);
         result
     }
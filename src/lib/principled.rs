@@ -0,0 +1,181 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use rand_distr::{Distribution, UnitSphere};
+
+use crate::hittable::HitInteraction;
+use crate::microfacet::Ggx;
+use crate::{common, Color, Dir3, Texture};
+
+// A Disney style uber material. Scalar parameters are in [0, 1] and read from the red channel of
+// their texture. The specular lobe is shared between the dielectric and the metallic part, the
+// transmission is a rough dielectric with the same roughness whose transmitted light is tinted
+// by the base color, and the clearcoat is a fixed index of refraction 1.5 layer on top.
+#[derive(Debug, Clone)]
+pub struct Principled<'a> {
+    pub base_color: &'a Texture<'a>,
+    pub metallic: &'a Texture<'a>,
+    pub roughness: &'a Texture<'a>,
+    // 0.5 is a reflectance of 4% at normal incidence
+    pub specular: &'a Texture<'a>,
+    // Tints the dielectric specular reflection towards the hue of the base color
+    pub specular_tint: &'a Texture<'a>,
+    // Grazing retro reflection for cloth
+    pub sheen: &'a Texture<'a>,
+    pub clearcoat: &'a Texture<'a>,
+    pub clearcoat_roughness: &'a Texture<'a>,
+    pub transmission: &'a Texture<'a>,
+    pub index_of_refraction: f32,
+}
+
+// The parameters at a hit
+struct Parameters {
+    base_color: Color,
+    metallic: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    clearcoat: f32,
+    transmission: f32,
+}
+
+// The lobes of a principled material at a hit, in the local frame of the surface
+#[derive(Debug, Clone, Copy)]
+pub struct PrincipledLobes {
+    pub specular: Ggx,
+    pub clearcoat: Ggx,
+    // The index of refraction below the surface divided by the one above
+    pub eta: f32,
+    // Of sampling the diffuse, specular, transmission and clearcoat lobe
+    pub probabilities: [f32; 4],
+}
+
+impl PrincipledLobes {
+    pub fn generate(&self, wo: Dir3, rng: &mut common::TRng) -> Dir3 {
+        let [diffuse, specular, transmission, _] = self.probabilities;
+        let u = rng.gen::<f32>();
+        if u < diffuse {
+            let normal = Dir3::new(0.0, 0.0, 1.0);
+            (normal + Dir3::new_from_arr(UnitSphere.sample(rng))).unit_or_else(normal)
+        } else if u < diffuse + specular {
+            Dir3::reflect(-wo, self.specular.sample_visible_normal(wo, rng))
+        } else if u < diffuse + specular + transmission {
+            self.specular.sample_dielectric(wo, self.eta, rng)
+        } else {
+            Dir3::reflect(-wo, self.clearcoat.sample_visible_normal(wo, rng))
+        }
+    }
+
+    // Density of generate per solid angle of wi
+    pub fn pdf(&self, wo: Dir3, wi: Dir3) -> f32 {
+        let [diffuse, specular, transmission, clearcoat] = self.probabilities;
+        diffuse * wi.0.e[2].max(0.0) / PI
+            + specular * self.specular.reflection_pdf(wo, wi)
+            + transmission * self.specular.dielectric_pdf(wo, wi, self.eta)
+            + clearcoat * self.clearcoat.reflection_pdf(wo, wi)
+    }
+}
+
+// The roughness is clamped, so that every lobe stays continuous
+fn ggx(roughness: f32) -> Ggx {
+    Ggx {
+        alpha: (roughness * roughness).max(Ggx::MIN_ALPHA),
+    }
+}
+
+// The Schlick approximation of the Fresnel reflectance with the given reflectance at normal
+// incidence
+fn schlick(f0: Color, cos_theta: f32) -> Color {
+    let weight = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    f0 * (1.0 - weight) + Color::WHITE * weight
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+impl<'a> Principled<'a> {
+    fn scalar(texture: &Texture, interaction: &HitInteraction) -> f32 {
+        texture.sample(interaction).0.e[0].clamp(0.0, 1.0)
+    }
+
+    fn parameters(&self, interaction: &HitInteraction) -> Parameters {
+        Parameters {
+            base_color: self.base_color.sample(interaction),
+            metallic: Self::scalar(self.metallic, interaction),
+            specular: Self::scalar(self.specular, interaction),
+            specular_tint: Self::scalar(self.specular_tint, interaction),
+            sheen: Self::scalar(self.sheen, interaction),
+            clearcoat: Self::scalar(self.clearcoat, interaction),
+            transmission: Self::scalar(self.transmission, interaction),
+        }
+    }
+
+    pub fn lobes(&self, interaction: &HitInteraction) -> PrincipledLobes {
+        let p = self.parameters(interaction);
+        let opaque = (1.0 - p.metallic) * (1.0 - p.transmission);
+        let weights = [
+            opaque,
+            p.metallic + 0.5 * opaque,
+            (1.0 - p.metallic) * p.transmission,
+            0.25 * p.clearcoat,
+        ];
+        // The first three weights sum up to at least one, so the division is safe
+        let sum: f32 = weights.iter().sum();
+        PrincipledLobes {
+            specular: ggx(Self::scalar(self.roughness, interaction)),
+            clearcoat: ggx(Self::scalar(self.clearcoat_roughness, interaction)),
+            eta: if interaction.front_face {
+                self.index_of_refraction
+            } else {
+                1.0 / self.index_of_refraction
+            },
+            probabilities: weights.map(|w| w / sum),
+        }
+    }
+
+    // The scattering function times |cos wi| per channel, both directions are in the local frame
+    // of the surface.
+    pub fn value(&self, wo: Dir3, wi: Dir3, interaction: &HitInteraction) -> Color {
+        let (cos_o, cos_i) = (wo.0.e[2], wi.0.e[2]);
+        if cos_o <= 0.0 {
+            return Color::BLACK;
+        }
+        let p = self.parameters(interaction);
+        let lobes = self.lobes(interaction);
+        let opaque = (1.0 - p.metallic) * (1.0 - p.transmission);
+        let glass = (1.0 - p.metallic) * p.transmission;
+
+        let mut value = Color::BLACK;
+        if glass > 0.0 {
+            // Light passing through a solid is tinted twice
+            let (reflected, transmitted) = lobes.specular.dielectric_value(wo, wi, lobes.eta);
+            let tint = Color::new_rgb_arr(p.base_color.0.e.map(|c| c.max(0.0).sqrt()));
+            value = (Color::WHITE * reflected + tint * transmitted) * glass;
+        }
+        if cos_i <= 0.0 {
+            return value;
+        }
+
+        let h = (wo + wi).unit();
+        let cos_d = Dir3::dot(wi, h);
+        let luminance = p.base_color.luminance();
+        let tint = if luminance > 0.0 {
+            p.base_color / luminance
+        } else {
+            Color::WHITE
+        };
+
+        let diffuse = p.base_color * (cos_i / PI);
+        // With the default sheen tint of the Disney model
+        let sheen = mix(Color::WHITE, tint, 0.5) * (p.sheen * (1.0 - cos_d).powi(5) * cos_i);
+        let f0 = mix(Color::WHITE, tint, p.specular_tint) * (0.08 * p.specular);
+        let fresnel = schlick(f0, cos_d) * opaque + schlick(p.base_color, cos_d) * p.metallic;
+        let specular = fresnel * (lobes.specular.d(h) * lobes.specular.g(wo, wi) / (4.0 * cos_o));
+        let clearcoat = schlick(Color::WHITE * 0.04, cos_d)
+            * (0.25 * p.clearcoat * lobes.clearcoat.d(h) * lobes.clearcoat.g(wo, wi)
+                / (4.0 * cos_o));
+
+        value + (diffuse + sheen) * opaque + specular + clearcoat
+    }
+}
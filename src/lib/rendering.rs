@@ -5,7 +5,7 @@ use crate::{
     background_color::BackgroundColor,
     color::Color,
     common::{self, TRng},
-    Camera, Dir3, MaterialScatteringDistribution, Ray, Scene, Size2i, Vec2f,
+    Camera, HitInteraction, MaterialScatteringDistribution, Point3, Ray, Scene, Size2i, Vec2f,
     WorldScatteringDistribution, WorldScatteringDistributionProvider,
 };
 
@@ -16,78 +16,185 @@ pub struct World<'a> {
     pub scattering_distribution_provider: Option<WorldScatteringDistributionProvider>,
 }
 
-fn ray_color<'a>(ray: &Ray, world: &World<'a>, rng: &mut TRng, max_depth: i32) -> Color {
-    let mut depth = max_depth;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisHeuristic {
+    Balance,
+    Power,
+}
+
+impl MisHeuristic {
+    // The weight of a sample generated with pdf, when other_pdf could have generated it too.
+    fn weight(self, pdf: f32, other_pdf: f32) -> f32 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub samples_per_pixel: usize,
+    pub max_depth: i32,
+    pub render_mode: RenderMode,
+    pub mis_heuristic: MisHeuristic,
+    // Paths with at least this many bounces are randomly terminated based on their throughput
+    pub russian_roulette_min_depth: Option<i32>,
+}
+
+impl RenderSettings {
+    pub fn new(samples_per_pixel: usize, max_depth: i32) -> Self {
+        Self {
+            samples_per_pixel,
+            max_depth,
+            render_mode: RenderMode::Default,
+            mis_heuristic: MisHeuristic::Power,
+            russian_roulette_min_depth: Some(3),
+        }
+    }
+}
+
+fn ray_color<'a>(ray: &Ray, world: &World<'a>, rng: &mut TRng, settings: &RenderSettings) -> Color {
+    let mut depth = settings.max_depth;
     let mut accum_attentuation: Color = Color::WHITE;
     let mut accum_emitted: Color = Color::BLACK;
     let mut cur_ray = *ray;
+    // The pdf with which the material sampled cur_ray, None if there was no light sampling at its origin
+    let mut material_pdf: Option<f32> = None;
     loop {
-        if let Some(interaction) = world.hittable.hit(&cur_ray, &(0.001..f32::INFINITY), rng) {
-            if depth <= 1 {
-                return Color::BLACK;
-            } else if let Some((attentuation, material_scattering_distribution)) =
-                interaction.material.scatter(&cur_ray, &interaction, rng)
+        let Some(interaction) = world.hittable.hit(&cur_ray, &(0.001..f32::INFINITY), rng) else {
+            let emitted = world.background.sample(&cur_ray);
+            return accum_emitted + Color::convolution(accum_attentuation, emitted);
+        };
+
+        let emitted = interaction.material.emit(&interaction);
+        if emitted != Color::BLACK {
+            let weight = match material_pdf {
+                Some(pdf) => {
+                    let light_pdf = world_scattering_distribution(world, &cur_ray.origin)
+                        .map_or(0.0, |d| d.value(cur_ray.direction));
+                    settings.mis_heuristic.weight(pdf, light_pdf)
+                }
+                None => 1.0,
+            };
+            accum_emitted += Color::convolution(accum_attentuation, emitted) * weight;
+        }
+
+        if depth <= 1 {
+            return accum_emitted;
+        }
+        let Some((attentuation, material_scattering_distribution)) =
+            interaction.material.scatter(&cur_ray, &interaction, rng)
+        else {
+            return accum_emitted;
+        };
+
+        let scattered = if material_scattering_distribution.is_discrete() {
+            let scattered_dir = material_scattering_distribution.generate(rng);
+            accum_attentuation = Color::convolution(accum_attentuation, attentuation);
+            material_pdf = None;
+            Ray::new(interaction.position, scattered_dir, cur_ray.time)
+        } else {
+            if let Some(world_scattering_distribution) =
+                world_scattering_distribution(world, &interaction.position)
             {
-                let (scattered, probablity) = if material_scattering_distribution.is_discrete() {
-                    let scattered_dir = material_scattering_distribution.generate(rng);
-                    let scattered = Ray::new(interaction.position, scattered_dir, cur_ray.time);
-                    (scattered, 1.0)
-                } else {
-                    let world_scattering_distribution = world
-                        .scattering_distribution_provider
-                        .as_ref()
-                        .and_then(|p| p.generate(&interaction.position));
-                    let (scattered_pdf, scattered_dir) = sample_final_scattering_distribution(
-                        &world_scattering_distribution,
-                        &material_scattering_distribution,
-                        rng,
-                    );
-                    let scattered = Ray::new(interaction.position, scattered_dir, cur_ray.time);
-                    let scattering_pdf =
-                        interaction
-                            .material
-                            .scattering_pdf(&cur_ray, &scattered, &interaction);
-                    let probablity = scattering_pdf / scattered_pdf;
-                    (scattered, probablity)
-                };
+                let light = sample_light(
+                    world,
+                    &cur_ray,
+                    &interaction,
+                    &world_scattering_distribution,
+                    &material_scattering_distribution,
+                    settings.mis_heuristic,
+                    rng,
+                );
+                accum_emitted +=
+                    Color::convolution(Color::convolution(accum_attentuation, attentuation), light);
+            }
 
-                let emitted = interaction.material.emit(&interaction);
-
-                accum_emitted += Color::convolution(accum_attentuation, emitted);
-                accum_attentuation =
-                    Color::convolution(accum_attentuation, attentuation) * probablity;
-                cur_ray = scattered;
-                depth -= 1;
-                continue;
-            } else {
-                let emitted = interaction.material.emit(&interaction);
-                return accum_emitted + Color::convolution(accum_attentuation, emitted);
+            let scattered_dir = material_scattering_distribution.generate(rng);
+            let scattered = Ray::new(interaction.position, scattered_dir, cur_ray.time);
+            let pdf = material_scattering_distribution.value(scattered_dir);
+            let scattering_pdf =
+                interaction
+                    .material
+                    .scattering_pdf(&cur_ray, &scattered, &interaction);
+            if pdf <= 0.0 || scattering_pdf.max_component() <= 0.0 {
+                return accum_emitted;
             }
-        } else {
-            let emitted = world.background.sample(ray);
-            return accum_emitted + Color::convolution(accum_attentuation, emitted);
+            accum_attentuation = Color::convolution(
+                Color::convolution(accum_attentuation, attentuation),
+                scattering_pdf,
+            ) / pdf;
+            material_pdf = Some(pdf);
+            scattered
+        };
+
+        if settings
+            .russian_roulette_min_depth
+            .is_some_and(|min_depth| settings.max_depth - depth >= min_depth)
+        {
+            // Surviving paths are scaled up by the inverse survival chance to stay unbiased
+            let survival = accum_attentuation.max_component().min(0.95);
+            if rng.gen::<f32>() >= survival {
+                return accum_emitted;
+            }
+            accum_attentuation *= 1.0 / survival;
         }
+
+        cur_ray = scattered;
+        depth -= 1;
     }
 }
 
-fn sample_final_scattering_distribution(
-    world: &Option<WorldScatteringDistribution>,
-    material: &MaterialScatteringDistribution,
+fn world_scattering_distribution<'a>(
+    world: &'a World,
+    origin: &Point3,
+) -> Option<WorldScatteringDistribution<'a>> {
+    world
+        .scattering_distribution_provider
+        .as_ref()
+        .and_then(|p| p.generate(origin))
+}
+
+// Next event estimation: Traces a shadow ray towards a sampled light, weighted against the
+// chance that the material would have sampled the same direction.
+fn sample_light(
+    world: &World,
+    ray: &Ray,
+    interaction: &HitInteraction,
+    world_scattering_distribution: &WorldScatteringDistribution,
+    material_scattering_distribution: &MaterialScatteringDistribution,
+    mis_heuristic: MisHeuristic,
     rng: &mut common::TRng,
-) -> (f32, Dir3) {
-    if let Some(world) = world {
-        let mix: f32 = 0.5;
-        let direction = if rng.gen_bool(mix as f64) {
-            world.generate(rng)
-        } else {
-            material.generate(rng)
-        };
-        let p = mix * world.value(direction) + (1.0 - mix) * material.value(direction);
-        (p, direction)
-    } else {
-        let direction = material.generate(rng);
-        let p = material.value(direction);
-        (p, direction)
+) -> Color {
+    let light_dir = world_scattering_distribution.generate(rng);
+    let light_pdf = world_scattering_distribution.value(light_dir);
+    if light_pdf <= 0.0 {
+        return Color::BLACK;
+    }
+    let shadow_ray = Ray::new(interaction.position, light_dir, ray.time);
+    let scattering_pdf = interaction
+        .material
+        .scattering_pdf(ray, &shadow_ray, interaction);
+    if scattering_pdf.max_component() <= 0.0 {
+        return Color::BLACK;
+    }
+    match world
+        .hittable
+        .hit(&shadow_ray, &(0.001..f32::INFINITY), rng)
+    {
+        Some(light_interaction) => {
+            let emitted = light_interaction.material.emit(&light_interaction);
+            let material_pdf = material_scattering_distribution.value(light_dir);
+            let weight = mis_heuristic.weight(light_pdf, material_pdf);
+            Color::convolution(emitted, scattering_pdf) * (weight / light_pdf)
+        }
+        None => Color::BLACK,
     }
 }
 
@@ -103,10 +210,10 @@ impl RenderMode {
         ray: &Ray,
         world: &'a World<'a>,
         rng: &mut TRng,
-        max_depth: i32,
+        settings: &RenderSettings,
     ) -> Color {
         match self {
-            RenderMode::Default => ray_color(ray, world, rng, max_depth),
+            RenderMode::Default => ray_color(ray, world, rng, settings),
             RenderMode::Normals => {
                 if let Some(interaction) = world.hittable.hit(ray, &(0.001..f32::INFINITY), rng) {
                     Color((interaction.normal.0 + crate::Vec3::new(1.0, 1.0, 1.0)) * 0.5)
@@ -121,11 +228,11 @@ impl RenderMode {
 pub fn render(
     image_size: Size2i,
     thread_count: usize,
-    samples_per_pixel: usize,
-    max_depth: i32,
+    settings: &RenderSettings,
     world: &World,
-    render_mode: RenderMode,
 ) -> Vec<Color> {
+    let samples_per_pixel = settings.samples_per_pixel;
+    let render_mode = settings.render_mode;
     eprintln!("Start rendering...");
     let start_time = std::time::Instant::now();
 
@@ -173,7 +280,7 @@ pub fn render(
                             .map(|_| {
                                 let pix = fpix + sub_rng.sample(pixel_sample_distr_ref);
                                 let ray = world.camera.ray(&mut sub_rng, pix);
-                                render_mode.ray_color(&ray, world, &mut sub_rng, max_depth)
+                                render_mode.ray_color(&ray, world, &mut sub_rng, settings)
                             })
                             .sum::<Color>()
                             / real_samples_per_pixel as f32
@@ -1,4 +1,6 @@
-use crate::{color::Color, hittable::HitInteraction, perlin::Perlin, vec3::Point3};
+use crate::{
+    color::Color, hittable::HitInteraction, perlin::Perlin, tone_mapping::srgb_decode, vec3::Point3,
+};
 
 #[derive(Debug, Clone)]
 pub enum Texture<'a> {
@@ -14,15 +16,82 @@ pub enum Texture<'a> {
         scale: f32,
         noise: Perlin,
     },
+    // The color channels of photos and painted textures are sRGB encoded, the ones of data like
+    // normal maps are linear. Alpha is always linear.
     Image {
-        image: &'a image::RgbImage,
+        image: &'a image::RgbaImage,
+        srgb: bool,
+    },
+    // A single channel of the texture as gray, for packed scalar maps. 3 is the alpha channel.
+    Channel {
+        texture: &'a Texture<'a>,
+        channel: usize,
+    },
+    // The texture multiplied by a color and its opacity by alpha, like the factors of glTF
+    Scaled {
+        texture: &'a Texture<'a>,
+        factor: Color,
+        alpha: f32,
+    },
+    // The vertex colors of the hit mesh, average is their mean for estimates
+    VertexColor {
+        average: Color,
     },
 }
 
 impl<'a> Texture<'a> {
+    // The mean color over the whole texture, used for estimates like the power of a light.
+    pub fn average(&self) -> Color {
+        match self {
+            Texture::Solid { color } => *color,
+            Texture::Checker { even, odd, .. } => (even.average() + odd.average()) * 0.5,
+            Texture::Marble { .. } => Color::new_rgb(0.5, 0.5, 0.5),
+            Texture::Image { image, srgb } => {
+                let sum = image.pixels().map(|p| rgb(p, *srgb)).sum::<Color>();
+                sum / (image.width() * image.height()).max(1) as f32
+            }
+            Texture::Channel {
+                texture,
+                channel: 3,
+            } => Color::WHITE * texture.average_alpha(),
+            Texture::Channel { texture, channel } => Color::WHITE * texture.average().0.e[*channel],
+            Texture::Scaled {
+                texture, factor, ..
+            } => Color::convolution(texture.average(), *factor),
+            Texture::VertexColor { average } => *average,
+        }
+    }
+
     pub fn sample(&self, interaction: &HitInteraction) -> Color {
         match self {
             Texture::Solid { color } => *color,
+            Texture::Checker { .. } => self.checker_cell(interaction).sample(interaction),
+            Texture::Image { image, srgb } => rgb(pixel(image, interaction), *srgb),
+            Texture::Channel {
+                texture,
+                channel: 3,
+            } => Color::WHITE * texture.alpha(interaction),
+            Texture::Channel { texture, channel } => {
+                Color::WHITE * texture.sample(interaction).0.e[*channel]
+            }
+            Texture::Scaled {
+                texture, factor, ..
+            } => Color::convolution(texture.sample(interaction), *factor),
+            Texture::VertexColor { .. } => interaction.color,
+            Texture::Marble { scale, noise } => {
+                Color::new_rgb(1.0, 1.0, 1.0)
+                    * 0.5
+                    * (1.0
+                        + (interaction.position.0.e[2] * *scale
+                            + 10.0 * noise.turbulence(interaction.position, 7, 0.5))
+                        .sin())
+            }
+        }
+    }
+
+    // The texture of the checker cell at the interaction
+    fn checker_cell(&self, interaction: &HitInteraction) -> &Texture<'a> {
+        match self {
             Texture::Checker {
                 inv_frequency: frequency,
                 even,
@@ -30,25 +99,58 @@ impl<'a> Texture<'a> {
             } => {
                 let s = (interaction.position - Point3::ORIGIN) * *frequency;
                 let sines = s.right().sin() * s.up().sin() * s.forward().sin();
-                let t = if sines < 0.0 { even } else { odd };
-                t.sample(interaction)
+                if sines < 0.0 {
+                    even
+                } else {
+                    odd
+                }
             }
-            Texture::Image { image } => {
-                let pix_u =
-                    ((interaction.uv.x * image.width() as f32) as u32).min(image.width() - 1);
-                let pix_v =
-                    ((interaction.uv.y * image.height() as f32) as u32).min(image.height() - 1);
+            _ => self,
+        }
+    }
+
+    // The opacity, which only images have, possibly scaled
+    pub fn alpha(&self, interaction: &HitInteraction) -> f32 {
+        match self {
+            Texture::Image { image, .. } => pixel(image, interaction).0[3] as f32 / 255.0,
+            Texture::Checker { .. } => self.checker_cell(interaction).alpha(interaction),
+            Texture::Scaled { texture, alpha, .. } => texture.alpha(interaction) * alpha,
+            _ => 1.0,
+        }
+    }
 
-                Color::new_rgb8(image.get_pixel(pix_u, pix_v).0)
+    fn average_alpha(&self) -> f32 {
+        match self {
+            Texture::Image { image, .. } => {
+                let sum = image.pixels().map(|p| p.0[3] as f32 / 255.0).sum::<f32>();
+                sum / (image.width() * image.height()).max(1) as f32
             }
-            Texture::Marble { scale, noise } => {
-                Color::new_rgb(1.0, 1.0, 1.0)
-                    * 0.5
-                    * (1.0
-                        + (interaction.position.0.e[2] * *scale
-                            + 10.0 * noise.turbulence(interaction.position, 7, 0.5))
-                        .sin())
+            Texture::Checker { even, odd, .. } => {
+                0.5 * (even.average_alpha() + odd.average_alpha())
             }
+            Texture::Scaled { texture, alpha, .. } => texture.average_alpha() * alpha,
+            _ => 1.0,
         }
     }
 }
+
+// Texture coordinates outside of [0, 1) repeat the image
+fn pixel<'i>(image: &'i image::RgbaImage, interaction: &HitInteraction) -> &'i image::Rgba<u8> {
+    let (u, v) = (
+        interaction.uv.x.rem_euclid(1.0),
+        interaction.uv.y.rem_euclid(1.0),
+    );
+    let pix_u = ((u * image.width() as f32) as u32).min(image.width() - 1);
+    let pix_v = ((v * image.height() as f32) as u32).min(image.height() - 1);
+    image.get_pixel(pix_u, pix_v)
+}
+
+fn rgb(pixel: &image::Rgba<u8>, srgb: bool) -> Color {
+    let [r, g, b, _] = pixel.0;
+    let color = Color::new_rgb8([r, g, b]);
+    if srgb {
+        Color::new_rgb_arr(color.0.e.map(srgb_decode))
+    } else {
+        color
+    }
+}
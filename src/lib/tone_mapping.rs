@@ -0,0 +1,128 @@
+use std::str::FromStr;
+
+use crate::{color::Color, math};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMappingOperator {
+    // Keeps the values and clamps them to [0, 1]
+    Clamp,
+    // c / (1 + c)
+    Reinhard,
+    // Reinhard which maps white_point to 1 instead of infinity
+    ExtendedReinhard { white_point: f32 },
+    // Narkowicz' fit of the ACES filmic reference curve
+    Aces,
+    // Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+// Maps linear radiance to display values: Scales by the exposure, applies the operator and
+// encodes the result with the sRGB transfer function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    // Exposure in stops, every stop doubles the brightness
+    pub exposure: f32,
+    pub operator: ToneMappingOperator,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneMappingOperator::Clamp,
+        }
+    }
+}
+
+impl ToneMapping {
+    // Returns linear display values in [0, 1].
+    pub fn apply(&self, color: Color) -> Color {
+        let scale = self.exposure.exp2();
+        Color::new_rgb_arr(color.0.e.map(|v| {
+            let v = (v * scale).max(0.0);
+            math::clamp(0.0, 1.0, self.operator.apply(v))
+        }))
+    }
+
+    pub fn to_rgb8(&self, color: Color) -> [u8; 3] {
+        self.apply(color)
+            .0
+            .e
+            .map(|v| math::clamp(0.0, 255.0, srgb_encode(v) * 256.0) as u8)
+    }
+}
+
+impl ToneMappingOperator {
+    fn apply(self, v: f32) -> f32 {
+        match self {
+            ToneMappingOperator::Clamp => v,
+            ToneMappingOperator::Reinhard => v / (1.0 + v),
+            ToneMappingOperator::ExtendedReinhard { white_point } => {
+                v * (1.0 + v / (white_point * white_point)) / (1.0 + v)
+            }
+            ToneMappingOperator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (v * (a * v + b)) / (v * (c * v + d) + e)
+            }
+            ToneMappingOperator::Hable => {
+                const EXPOSURE_BIAS: f32 = 2.0;
+                const WHITE_POINT: f32 = 11.2;
+                hable_partial(v * EXPOSURE_BIAS) / hable_partial(WHITE_POINT)
+            }
+        }
+    }
+}
+
+fn hable_partial(v: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((v * (a * v + c * b) + d * e) / (v * (a * v + b) + d * f)) - e / f
+}
+
+// The sRGB transfer function for linear values in [0, 1].
+pub fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_decode(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl FromStr for ToneMappingOperator {
+    type Err = String;
+
+    // Accepts clamp, reinhard, reinhard_extended[:white_point], aces and hable.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        match (name, parameter) {
+            ("clamp", None) => Ok(ToneMappingOperator::Clamp),
+            ("reinhard", None) => Ok(ToneMappingOperator::Reinhard),
+            ("reinhard_extended", parameter) => {
+                let white_point = match parameter {
+                    Some(p) => p
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|w| *w > 0.0)
+                        .ok_or_else(|| format!("Invalid white point '{p}'"))?,
+                    None => 4.0,
+                };
+                Ok(ToneMappingOperator::ExtendedReinhard { white_point })
+            }
+            ("aces", None) => Ok(ToneMappingOperator::Aces),
+            ("hable", None) => Ok(ToneMappingOperator::Hable),
+            _ => Err(format!(
+                "Unknown tone mapping '{s}', expected clamp, reinhard, reinhard_extended[:white_point], aces or hable"
+            )),
+        }
+    }
+}
@@ -1,18 +1,75 @@
 use crate::vec3::{Dir3, Point3, Vec3};
 
+// Row major 3x3 matrix
+type Mat3 = [[f32; 3]; 3];
+
+const IDENTITY: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    pub fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+    pub fn from_axis_angle(axis: Dir3, angle: f32) -> Self {
+        let (s, c) = (angle.to_radians() * 0.5).sin_cos();
+        let axis = axis.unit();
+        Self::new(c, axis.0.e[0] * s, axis.0.e[1] * s, axis.0.e[2] * s)
+    }
+    fn to_matrix(self) -> Mat3 {
+        let len = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        let (w, x, y, z) = (self.w / len, self.x / len, self.y / len, self.z / len);
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+}
+
+// An affine transformation, i.e. a linear part followed by a translation.
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Transformation {
+    linear: Mat3,
+    inverse_linear: Mat3,
     offset: Dir3,
-    y_sine: f32,
-    y_cosine: f32,
 }
 
 impl Transformation {
     pub const ZERO: Transformation = Transformation {
+        linear: IDENTITY,
+        inverse_linear: IDENTITY,
         offset: Dir3::ZERO,
-        y_sine: 0.0,
-        y_cosine: 1.0,
     };
+
+    // Creates the transformation p -> linear * p + offset, None if linear is not invertible.
+    pub fn new_affine(linear: [[f32; 3]; 3], offset: Dir3) -> Option<Self> {
+        let inverse_linear = mat_inverse(&linear)?;
+        Some(Self {
+            linear,
+            inverse_linear,
+            offset,
+        })
+    }
+
     pub fn translate_xyz(&self, x: f32, y: f32, z: f32) -> Self {
         self.translate(Dir3::new(x, y, z))
     }
@@ -24,35 +81,85 @@ impl Transformation {
     pub fn is_zero(&self) -> bool {
         *self == Self::ZERO
     }
+    pub fn scale(&self, factors: Dir3) -> Self {
+        let [x, y, z] = factors.0.e;
+        assert!(x != 0.0 && y != 0.0 && z != 0.0, "Scale must not be zero");
+        self.then_linear(
+            [[x, 0.0, 0.0], [0.0, y, 0.0], [0.0, 0.0, z]],
+            [
+                [1.0 / x, 0.0, 0.0],
+                [0.0, 1.0 / y, 0.0],
+                [0.0, 0.0, 1.0 / z],
+            ],
+        )
+    }
+    pub fn scale_uniform(&self, factor: f32) -> Self {
+        self.scale(Dir3::new(factor, factor, factor))
+    }
     pub fn rotate_around_up(&self, angle: f32) -> Self {
-        let mut result = *self;
-        let (s, c) = angle.to_radians().sin_cos();
-        rotate_around_up(c, s, &mut result.offset.0);
-        let mut r = Vec3::new(self.y_sine, 0.0, self.y_cosine);
-        rotate_around_up(c, s, &mut r);
-        result.y_sine = r.e[0];
-        result.y_cosine = r.e[2];
-        result
+        self.rotate_axis(Dir3::UP, angle)
+    }
+    // Rotates counterclockwise by angle degrees around the axis.
+    pub fn rotate_axis(&self, axis: Dir3, angle: f32) -> Self {
+        self.rotate_quaternion(Quaternion::from_axis_angle(axis, angle))
+    }
+    // Rotates by the angles in degrees around the x, then the y and then the z axis.
+    pub fn rotate_euler(&self, x: f32, y: f32, z: f32) -> Self {
+        self.rotate_axis(Dir3::RIGHT, x)
+            .rotate_axis(Dir3::UP, y)
+            .rotate_axis(Dir3::BACKWARD, z)
+    }
+    pub fn rotate_quaternion(&self, rotation: Quaternion) -> Self {
+        let m = rotation.to_matrix();
+        self.then_rotation(m)
+    }
+    // Rotates around the current origin, so that the forward axis points towards target.
+    pub fn look_at(&self, target: Point3, up: Dir3) -> Self {
+        let origin = self.apply_point(Point3::ORIGIN);
+        let current = orthonormal_basis(self.apply_direction(Dir3::FORWARD), up);
+        let wanted = orthonormal_basis(target - origin, up);
+        let rotation = mat_mul(&wanted, &mat_transpose(&current));
+        self.translate(Point3::ORIGIN - origin)
+            .then_rotation(rotation)
+            .translate(origin - Point3::ORIGIN)
+    }
+
+    fn then_rotation(&self, rotation: Mat3) -> Self {
+        self.then_linear(rotation, mat_transpose(&rotation))
     }
+    fn then_linear(&self, linear: Mat3, inverse_linear: Mat3) -> Self {
+        self.then(&Transformation {
+            linear,
+            inverse_linear,
+            offset: Dir3::ZERO,
+        })
+    }
+
     pub fn apply_point_mut(&self, point: &mut Point3) {
-        rotate_around_up(self.y_cosine, self.y_sine, &mut point.0);
+        point.0 = mat_mul_vec(&self.linear, point.0);
         *point += self.offset;
     }
 
+    // Normals are transformed with the inverse transpose, the result is normalized.
     pub fn apply_normal_mut(&self, dir: &mut Dir3) {
-        rotate_around_up(self.y_cosine, self.y_sine, &mut dir.0);
+        dir.0 = mat_mul_vec_transposed(&self.inverse_linear, dir.0);
+        *dir = dir.unit_or_else(*dir);
     }
     pub fn apply_direction_mut(&self, dir: &mut Dir3) {
-        rotate_around_up(self.y_cosine, self.y_sine, &mut dir.0);
+        dir.0 = mat_mul_vec(&self.linear, dir.0);
     }
 
     pub fn reverse_point_mut(&self, point: &mut Point3) {
         *point -= self.offset;
-        rotate_around_up(self.y_cosine, -self.y_sine, &mut point.0);
+        point.0 = mat_mul_vec(&self.inverse_linear, point.0);
     }
 
     pub fn reverse_normal_mut(&self, dir: &mut Dir3) {
-        rotate_around_up(self.y_cosine, -self.y_sine, &mut dir.0);
+        dir.0 = mat_mul_vec_transposed(&self.linear, dir.0);
+        *dir = dir.unit_or_else(*dir);
+    }
+    pub fn reverse_direction_mut(&self, dir: &mut Dir3) {
+        dir.0 = mat_mul_vec(&self.inverse_linear, dir.0);
     }
 
     pub fn apply_point(&self, mut point: Point3) -> Point3 {
@@ -80,20 +187,39 @@ impl Transformation {
         self.reverse_normal_mut(&mut dir);
         dir
     }
-    pub fn apply_distance(&self, distance: f32) -> f32 {
-        distance
+    pub fn reverse_direction(&self, mut dir: Dir3) -> Dir3 {
+        self.reverse_direction_mut(&mut dir);
+        dir
+    }
+    // The common scale factor, if the linear part is a rotation combined with a uniform scale.
+    pub fn uniform_scale(&self) -> Option<f32> {
+        let m = mat_mul(&mat_transpose(&self.linear), &self.linear);
+        let scale_squared = m[0][0];
+        let eps = 1e-5 * scale_squared;
+        for (i, row) in m.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                let expected = if i == j { scale_squared } else { 0.0 };
+                if (v - expected).abs() > eps {
+                    return None;
+                }
+            }
+        }
+        Some(scale_squared.sqrt())
+    }
+    // The linear part is a diagonal matrix, i.e. axis aligned boxes stay axis aligned.
+    pub fn is_axis_aligned_scale(&self) -> bool {
+        (0..3).all(|i| (0..3).all(|j| i == j || self.linear[i][j] == 0.0))
     }
-    pub fn reverse_distance(&self, distance: f32) -> f32 {
-        distance
+    // The absolute determinant of the linear part, i.e. the factor by which volumes are scaled.
+    pub fn volume_scale(&self) -> f32 {
+        mat_determinant(&self.linear).abs()
     }
     pub fn then(&self, next: &Transformation) -> Transformation {
         let offset = next.apply_direction(self.offset) + next.offset;
-        let y_sine = self.y_sine * next.y_cosine + self.y_cosine * next.y_sine;
-        let y_cosine = self.y_cosine * next.y_cosine - self.y_sine * next.y_sine;
         Transformation {
+            linear: mat_mul(&next.linear, &self.linear),
+            inverse_linear: mat_mul(&self.inverse_linear, &next.inverse_linear),
             offset,
-            y_sine,
-            y_cosine,
         }
     }
 }
@@ -104,8 +230,86 @@ impl Default for Transformation {
     }
 }
 
-fn rotate_around_up(c: f32, s: f32, v: &mut Vec3<f32>) {
-    let (x, y) = (v.e[0], v.e[2]);
-    v.e[0] = c * x + s * y;
-    v.e[2] = -s * x + c * y;
+// Columns are right, up and backward of the frame looking along forward.
+fn orthonormal_basis(forward: Dir3, up: Dir3) -> Mat3 {
+    let forward = forward.unit();
+    let right = Dir3::cross(forward, up).unit_or_else(Dir3::cross(forward, Dir3::RIGHT).unit());
+    let up = Dir3::cross(right, forward);
+    let backward = -forward;
+    [
+        [right.0.e[0], up.0.e[0], backward.0.e[0]],
+        [right.0.e[1], up.0.e[1], backward.0.e[1]],
+        [right.0.e[2], up.0.e[2], backward.0.e[2]],
+    ]
+}
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    result
+}
+
+fn mat_transpose(m: &Mat3) -> Mat3 {
+    [
+        [m[0][0], m[1][0], m[2][0]],
+        [m[0][1], m[1][1], m[2][1]],
+        [m[0][2], m[1][2], m[2][2]],
+    ]
+}
+
+fn mat_mul_vec(m: &Mat3, v: Vec3<f32>) -> Vec3<f32> {
+    let [x, y, z] = v.e;
+    Vec3::new(
+        m[0][0] * x + m[0][1] * y + m[0][2] * z,
+        m[1][0] * x + m[1][1] * y + m[1][2] * z,
+        m[2][0] * x + m[2][1] * y + m[2][2] * z,
+    )
+}
+
+fn mat_mul_vec_transposed(m: &Mat3, v: Vec3<f32>) -> Vec3<f32> {
+    let [x, y, z] = v.e;
+    Vec3::new(
+        m[0][0] * x + m[1][0] * y + m[2][0] * z,
+        m[0][1] * x + m[1][1] * y + m[2][1] * z,
+        m[0][2] * x + m[1][2] * y + m[2][2] * z,
+    )
+}
+
+fn mat_determinant(m: &Mat3) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn mat_inverse(m: &Mat3) -> Option<Mat3> {
+    let det = mat_determinant(m);
+    // Zero, tiny or not finite
+    if !det.is_normal() {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+        (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) * inv_det
+    };
+    Some([
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ])
 }
@@ -229,6 +229,18 @@ impl Dir3 {
         }
     }
 
+    // Two directions which form a right handed orthonormal basis with the unit vector self.
+    pub fn orthonormal_basis(self) -> (Dir3, Dir3) {
+        let [x, y, z] = self.0.e;
+        let sign = 1.0f32.copysign(z);
+        let a = -1.0 / (sign + z);
+        let b = x * y * a;
+        (
+            Dir3::new(1.0 + sign * x * x * a, sign * b, -sign * x),
+            Dir3::new(b, sign + y * y * a, -y),
+        )
+    }
+
     pub fn reflect(ray: Dir3, normal: Dir3) -> Dir3 {
         ray - (2.0 * Self::dot(ray, normal)) * normal
     }
@@ -1,11 +1,20 @@
-use crate::hittable::rect_geometry::RectGeometry;
-use crate::{common, Dir3, Point3};
+use rand::Rng;
+
+use crate::hittable::{
+    mesh_geometry::MeshSamplingGeometry, rect_geometry::RectGeometry,
+    sphere_geometry::SphereGeometry, triangle_geometry::TriangleGeometry,
+};
+use crate::{common, Aabb, Dir3, Point3, Transformation};
 
 pub enum WorldScatteringDistributionProvider {
     Rect(RectGeometry),
-    //Sphere(SphereGeometry),
-    //AxisAlignedBox(Aabb),
-    //List(Vec<Box<WorldScatteringDistributionProvider>>)
+    Sphere(SphereGeometry),
+    AxisAlignedBox(Aabb),
+    Triangle(TriangleGeometry),
+    Mesh(MeshSamplingGeometry),
+    Transformation(Box<WorldScatteringDistributionProvider>, Transformation),
+    // Mixture of providers, the weights sum up to one
+    List(Vec<(WorldScatteringDistributionProvider, f32)>),
 }
 
 pub struct WorldScatteringDistribution<'a> {
@@ -14,23 +23,99 @@ pub struct WorldScatteringDistribution<'a> {
 }
 
 impl WorldScatteringDistributionProvider {
-    pub fn generate(&self, origin: &Point3) -> Option<WorldScatteringDistribution> {
+    // Creates a mixture of the providers, each one is choosen proportional to its weight.
+    pub fn new_list(
+        mut providers: Vec<(WorldScatteringDistributionProvider, f32)>,
+    ) -> Option<Self> {
+        let total_weight = providers.iter().map(|(_, w)| *w).sum::<f32>();
+        if providers.len() <= 1 || total_weight <= 0.0 {
+            return providers.pop().map(|(p, _)| p);
+        }
+        for (_, weight) in &mut providers {
+            *weight /= total_weight;
+        }
+        Some(WorldScatteringDistributionProvider::List(providers))
+    }
+
+    pub fn generate(&self, origin: &Point3) -> Option<WorldScatteringDistribution<'_>> {
         Some(WorldScatteringDistribution {
             provider: self,
             origin: *origin,
         })
     }
+
+    // The surface area of the emitting geometry, used to weight multiple providers.
+    pub fn area(&self) -> f32 {
+        match self {
+            WorldScatteringDistributionProvider::Rect(geo) => geo.area(),
+            WorldScatteringDistributionProvider::Sphere(geo) => geo.area(),
+            WorldScatteringDistributionProvider::AxisAlignedBox(geo) => geo.surface_area(),
+            WorldScatteringDistributionProvider::Triangle(geo) => geo.area(),
+            WorldScatteringDistributionProvider::Mesh(geo) => geo.area(),
+            WorldScatteringDistributionProvider::Transformation(provider, transformation) => {
+                provider.area() * transformation.volume_scale().powf(2.0 / 3.0)
+            }
+            WorldScatteringDistributionProvider::List(providers) => {
+                providers.iter().map(|(p, _)| p.area()).sum()
+            }
+        }
+    }
+
+    fn generate_dir(&self, origin: Point3, rng: &mut common::TRng) -> Dir3 {
+        match self {
+            WorldScatteringDistributionProvider::Rect(geo) => geo.generate(origin, rng),
+            WorldScatteringDistributionProvider::Sphere(geo) => geo.generate(origin, rng),
+            WorldScatteringDistributionProvider::AxisAlignedBox(geo) => geo.generate(origin, rng),
+            WorldScatteringDistributionProvider::Triangle(geo) => geo.generate(origin, rng),
+            WorldScatteringDistributionProvider::Mesh(geo) => geo.generate(origin, rng),
+            WorldScatteringDistributionProvider::Transformation(provider, transformation) => {
+                let local_origin = transformation.reverse_point(origin);
+                let local_dir = provider.generate_dir(local_origin, rng);
+                transformation.apply_direction(local_dir).unit()
+            }
+            WorldScatteringDistributionProvider::List(providers) => {
+                let mut choice = rng.gen::<f32>();
+                for (provider, weight) in providers {
+                    if choice < *weight {
+                        return provider.generate_dir(origin, rng);
+                    }
+                    choice -= weight;
+                }
+                providers.last().unwrap().0.generate_dir(origin, rng)
+            }
+        }
+    }
+
+    fn value(&self, origin: Point3, direction: Dir3) -> f32 {
+        match self {
+            WorldScatteringDistributionProvider::Rect(geo) => geo.value(origin, direction),
+            WorldScatteringDistributionProvider::Sphere(geo) => geo.value(origin, direction),
+            WorldScatteringDistributionProvider::AxisAlignedBox(geo) => {
+                geo.value(origin, direction)
+            }
+            WorldScatteringDistributionProvider::Triangle(geo) => geo.value(origin, direction),
+            WorldScatteringDistributionProvider::Mesh(geo) => geo.value(origin, direction),
+            WorldScatteringDistributionProvider::Transformation(provider, transformation) => {
+                // Change of variables for the direction d -> normalize(M^-1 * d)
+                let local_origin = transformation.reverse_point(origin);
+                let local_dir = transformation.reverse_direction(direction);
+                let stretch = local_dir.length();
+                let local_value = provider.value(local_origin, local_dir / stretch);
+                local_value / (transformation.volume_scale() * stretch * stretch * stretch)
+            }
+            WorldScatteringDistributionProvider::List(providers) => providers
+                .iter()
+                .map(|(provider, weight)| weight * provider.value(origin, direction))
+                .sum(),
+        }
+    }
 }
 
 impl<'a> WorldScatteringDistribution<'a> {
     pub fn generate(&self, rng: &mut common::TRng) -> Dir3 {
-        match self.provider {
-            WorldScatteringDistributionProvider::Rect(geo) => geo.generate(self.origin, rng),
-        }
+        self.provider.generate_dir(self.origin, rng)
     }
     pub fn value(&self, direction: Dir3) -> f32 {
-        match self.provider {
-            WorldScatteringDistributionProvider::Rect(geo) => geo.value(self.origin, direction),
-        }
+        self.provider.value(self.origin, direction)
     }
 }